@@ -0,0 +1,4 @@
+fn main() {
+    // The templates are embedded into the binary, so it needs to be rebuilt when they change.
+    println!("cargo:rerun-if-changed=templates");
+}
@@ -1,18 +1,25 @@
 use std::net::TcpListener;
 use std::sync::Arc;
+use std::time::Duration;
 
 use actix_web::dev::Server;
 use actix_web::{web, HttpServer};
 use anyhow::Result;
-use minijinja::Environment;
 use tracing::info;
 use tracing_actix_web::TracingLogger;
 
-use crate::config::{RepoSettings, Settings};
-use crate::routes::{health_check, home, webhook};
+use crate::commands::Commands;
+use crate::config::{AdminSettings, Settings};
+use crate::routes::{
+    health_check, home, leave_room, list_deliveries, list_mutes, list_rooms, list_subscriptions,
+    mute, preview, render_preview, subscribe, unmute, unsubscribe, webhook,
+};
 use crate::services::XMPPService;
-use crate::templates::get_environment;
-use crate::webhook::{RepoMapping, WorkflowRunsStore};
+use crate::templates::Templates;
+use crate::webhook::{
+    post_expired_mutes, sync_rooms, LabelChangesStore, MutesStore, RepoMapping, SubscriptionsStore,
+    ThreadsStore, WorkflowRunsStore,
+};
 
 pub struct App {
     server: Server,
@@ -37,13 +44,53 @@ impl App {
 
         info!("Started server on {port}.");
 
+        let templates = Templates::load(config.app.templates_dir.clone())?
+            .with_max_message_size(config.xmpp.max_message_size);
+
+        #[cfg(unix)]
+        tokio::spawn(crate::templates::reload_on_sighup(templates.clone()));
+
+        let data_file = |name: &str| {
+            config
+                .app
+                .data_dir
+                .as_ref()
+                .map(|data_dir| data_dir.join(name))
+        };
+        let repo_mapping = RepoMapping::with_subscriptions(
+            config.webhook.repos,
+            SubscriptionsStore::load(data_file("subscriptions.json"))?,
+        );
+        let mutes_store = MutesStore::load(data_file("mutes.json"))?;
+
+        xmpp_service.set_message_handler(Arc::new(Commands::new(
+            config.xmpp.admins,
+            repo_mapping.clone(),
+            mutes_store.clone(),
+        )));
+
+        let xmpp_service: Arc<dyn XMPPService> = Arc::new(xmpp_service);
+
+        tokio::spawn(sync_rooms(repo_mapping.clone(), xmpp_service.clone()));
+        tokio::spawn(post_expired_mutes(
+            mutes_store.clone(),
+            repo_mapping.clone(),
+            xmpp_service.clone(),
+        ));
+
         let server = run(
             listener,
-            Arc::new(xmpp_service),
+            xmpp_service,
             ApplicationBaseUrl(config.app.base_url),
-            config.webhook.repos,
-            WorkflowRunsStore::new(),
-            get_environment()?,
+            repo_mapping,
+            WorkflowRunsStore::new(Duration::from_secs(
+                config.webhook.workflow_correction_max_age_secs,
+            )),
+            LabelChangesStore::new(Duration::from_secs(config.webhook.label_changes_delay_secs)),
+            ThreadsStore::new(),
+            mutes_store,
+            templates,
+            config.admin,
         )?;
 
         Ok(Self { server, port })
@@ -60,19 +107,28 @@ impl App {
 
 pub struct ApplicationBaseUrl(pub String);
 
+#[allow(clippy::too_many_arguments)]
 pub fn run(
     listener: TcpListener,
     xmpp: Arc<dyn XMPPService>,
     base_url: ApplicationBaseUrl,
-    repo_settings: Vec<RepoSettings>,
+    repo_mapping: RepoMapping,
     workflow_runs_store: WorkflowRunsStore,
-    environment: Environment<'static>,
+    label_changes_store: LabelChangesStore,
+    threads_store: ThreadsStore,
+    mutes_store: MutesStore,
+    templates: Templates,
+    admin_settings: Option<AdminSettings>,
 ) -> Result<Server> {
     let xmpp = web::Data::new(xmpp);
     let base_url = web::Data::new(base_url);
-    let repo_mapping = web::Data::new(RepoMapping::new(repo_settings));
-    let environment = web::Data::new(environment);
+    let repo_mapping = web::Data::new(repo_mapping);
+    let templates = web::Data::new(templates);
     let workflow_runs_store = web::Data::new(workflow_runs_store);
+    let label_changes_store = web::Data::new(label_changes_store);
+    let threads_store = web::Data::new(threads_store);
+    let mutes_store = web::Data::new(mutes_store);
+    let admin_settings = admin_settings.map(web::Data::new);
 
     let server = HttpServer::new(move || {
         actix_web::App::new()
@@ -80,11 +136,30 @@ pub fn run(
             .route("/", web::get().to(home))
             .route("/health_check", web::get().to(health_check))
             .route("/webhook", web::post().to(webhook))
+            .route("/admin/preview", web::get().to(preview))
+            .route("/admin/preview", web::post().to(render_preview))
+            .route("/admin/mutes", web::get().to(list_mutes))
+            .route("/admin/mutes", web::post().to(mute))
+            .route("/admin/mutes", web::delete().to(unmute))
+            .route("/admin/subscriptions", web::get().to(list_subscriptions))
+            .route("/admin/subscriptions", web::post().to(subscribe))
+            .route("/admin/subscriptions", web::delete().to(unsubscribe))
+            .route("/admin/rooms", web::get().to(list_rooms))
+            .route("/admin/rooms", web::delete().to(leave_room))
+            .route("/admin/deliveries", web::get().to(list_deliveries))
             .app_data(xmpp.clone())
             .app_data(base_url.clone())
             .app_data(repo_mapping.clone())
             .app_data(workflow_runs_store.clone())
-            .app_data(environment.clone())
+            .app_data(label_changes_store.clone())
+            .app_data(threads_store.clone())
+            .app_data(mutes_store.clone())
+            .app_data(templates.clone())
+            .configure(|config| {
+                if let Some(admin_settings) = &admin_settings {
+                    config.app_data(admin_settings.clone());
+                }
+            })
     })
     .listen(listener)?
     .run();
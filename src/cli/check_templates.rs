@@ -0,0 +1,69 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+use minijinja::context;
+use serde_json::Value;
+
+use crate::cli::samples::SAMPLES;
+use crate::templates::{split_locale, Templates};
+
+/// Compiles all templates and renders each of them against the bundled sample payloads of its
+/// event. Fails if any template doesn't compile or render.
+pub fn check_templates(templates_dir: Option<PathBuf>) -> Result<()> {
+    let templates = Templates::load(templates_dir)?;
+    let environment = templates.environment();
+    let mut failures = 0;
+
+    for (name, template) in environment.templates() {
+        // Repository- and owner-specific as well as localized templates are checked against the
+        // samples of the template they override.
+        let (base_name, locale) = split_locale(name.rsplit('/').next().unwrap_or(name));
+        let samples = SAMPLES
+            .iter()
+            .filter(|sample| sample.template == base_name)
+            .collect::<Vec<_>>();
+
+        if samples.is_empty() {
+            println!("⚠️  {name}: no sample payload");
+            continue;
+        }
+
+        for sample in samples {
+            let event = serde_json::from_str::<Value>(sample.payload)?;
+
+            // The union of the context of all call sites, see `routes::webhook`.
+            let result = template.render(context!(
+                event => event,
+                event_type => sample.event_type,
+                added => ["enhancement"],
+                removed => ["bug"],
+                unsupported_events => Vec::<&str>::new(),
+                locale => locale,
+            ));
+
+            match result {
+                Ok(_) => println!("✅ {name} ({})", sample.name),
+                Err(err) => {
+                    failures += 1;
+                    println!("❌ {name} ({}): {err}", sample.name);
+                }
+            }
+        }
+    }
+
+    if failures > 0 {
+        bail!("{failures} template renders failed");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_built_in_templates_render_sample_payloads() {
+        check_templates(None).unwrap();
+    }
+}
@@ -0,0 +1,42 @@
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+
+pub use check_templates::check_templates;
+pub use render::render;
+
+mod check_templates;
+mod render;
+pub(crate) mod samples;
+
+/// Forwards GitHub webhook events to XMPP rooms.
+#[derive(Parser, Debug)]
+#[command(version)]
+pub struct Cli {
+    /// Runs the webhook server if no command is given.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Renders a webhook payload exactly like the webhook would and prints the messages that would
+    /// be sent.
+    Render {
+        /// The type of the event, i.e. the value of the `X-GitHub-Event` header.
+        #[arg(long)]
+        event: String,
+        /// The file containing the webhook payload.
+        #[arg(long)]
+        fixture: PathBuf,
+        /// Renders the payload as if it was sent for this repository, e.g. "owner/name".
+        #[arg(long)]
+        repo: Option<String>,
+    },
+    /// Compiles all templates and renders them against bundled sample payloads.
+    CheckTemplates {
+        /// The templates directory to check. Defaults to the configured `templates_dir`.
+        #[arg(long)]
+        templates_dir: Option<PathBuf>,
+    },
+}
@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use actix_web::body::MessageBody;
+use actix_web::web;
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+
+use crate::config::Settings;
+use crate::routes::handle_event;
+use crate::services::split_message::split_message;
+use crate::services::xmpp_handle::message_styling_fallback;
+use crate::services::xmpp_service::{OutgoingMessage, RoomId};
+use crate::services::XMPPService;
+use crate::templates::Templates;
+use crate::webhook::{LabelChangesStore, MutesStore, RepoMapping, ThreadsStore, WorkflowRunsStore};
+
+/// Renders the webhook payload in `fixture` like the webhook would and prints the messages that
+/// would be sent, both as markdown and as XEP-0393 message styling.
+///
+/// # Arguments
+/// * `config` - The configuration used to route the event.
+/// * `event_type` - The type of the event, i.e. the value of the `X-GitHub-Event` header.
+/// * `fixture` - The file containing the webhook payload.
+/// * `repo` - Replaces the repository of the payload, e.g. "owner/name".
+pub fn render(
+    config: Settings,
+    event_type: &str,
+    fixture: &Path,
+    repo: Option<&str>,
+) -> Result<()> {
+    let payload = fs::read_to_string(fixture)
+        .with_context(|| format!("Failed to read {}", fixture.display()))?;
+    let mut event = serde_json::from_str::<HashMap<String, Value>>(&payload)?;
+
+    if let Some(repo) = repo {
+        event
+            .entry("repository".to_string())
+            .or_insert_with(|| json!({}))["full_name"] = repo.into();
+    }
+
+    let recorder = Arc::new(RecordingXMPPService::default());
+    let xmpp: Arc<dyn XMPPService> = recorder.clone();
+    let templates = Templates::load(config.app.templates_dir)?
+        .with_max_message_size(config.xmpp.max_message_size);
+
+    // Label changes are reported right away, there's nobody waiting for further changes.
+    let response = handle_event(
+        event_type,
+        event,
+        &web::Data::new(xmpp),
+        &RepoMapping::new(config.webhook.repos),
+        &WorkflowRunsStore::new(Duration::ZERO),
+        &web::Data::new(LabelChangesStore::new(Duration::ZERO)),
+        &web::Data::new(ThreadsStore::new()),
+        &MutesStore::load(None)?,
+        &web::Data::new(templates),
+    )?;
+
+    let messages = recorder.messages.lock().unwrap();
+
+    if messages.is_empty() {
+        let status = response.status();
+        let body = response.into_body().try_into_bytes().unwrap_or_default();
+        println!(
+            "No message would be sent. The webhook responded with {status}: {}",
+            String::from_utf8_lossy(&body)
+        );
+        return Ok(());
+    }
+
+    // Long messages are split by the XMPP service, so show the parts that would actually be sent.
+    let parts = messages.iter().flat_map(|message| {
+        split_message(&message.body, config.xmpp.max_message_size)
+            .into_iter()
+            .map(move |part| (&message.to, part))
+    });
+
+    for (to, message) in parts {
+        let (RoomId::User(jid) | RoomId::Room(jid)) = to;
+        println!("To: {jid}");
+        println!();
+        println!("--- Markdown ---");
+        println!("{message}");
+        println!();
+        println!("--- XEP-0393 ---");
+        println!("{}", message_styling_fallback(&message));
+        println!();
+    }
+
+    Ok(())
+}
+
+/// Records messages instead of sending them.
+#[derive(Default)]
+struct RecordingXMPPService {
+    messages: Mutex<Vec<OutgoingMessage>>,
+}
+
+impl XMPPService for RecordingXMPPService {
+    fn send_message(&self, message: OutgoingMessage) {
+        self.messages.lock().unwrap().push(message);
+    }
+}
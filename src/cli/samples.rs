@@ -0,0 +1,152 @@
+/// A webhook payload the templates are checked against.
+pub struct Sample {
+    /// The name of the template the payload is rendered with.
+    pub template: &'static str,
+    /// The type of the event, i.e. the value of the `X-GitHub-Event` header.
+    pub event_type: &'static str,
+    /// The name of the file in `samples` the payload is read from.
+    pub name: &'static str,
+    pub payload: &'static str,
+}
+
+macro_rules! sample {
+    ($template:literal, $event_type:literal, $name:literal) => {
+        Sample {
+            template: $template,
+            event_type: $event_type,
+            name: $name,
+            payload: include_str!(concat!("../../samples/", $name, ".json")),
+        }
+    };
+}
+
+/// The payloads in `samples`, which the integration tests post as well. Events without a payload of their own
+/// borrow the payload of a closely related event.
+pub static SAMPLES: &[Sample] = &[
+    sample!("_default.md", "star", "star_created"),
+    sample!("_release.md", "release", "release_released_with_assets"),
+    sample!(
+        "code_scanning_alert__closed_by_user.md",
+        "code_scanning_alert",
+        "code_scanning_alert_created"
+    ),
+    sample!(
+        "code_scanning_alert__created.md",
+        "code_scanning_alert",
+        "code_scanning_alert_created"
+    ),
+    sample!(
+        "code_scanning_alert__fixed.md",
+        "code_scanning_alert",
+        "code_scanning_alert_created"
+    ),
+    sample!(
+        "code_scanning_alert__reopened.md",
+        "code_scanning_alert",
+        "code_scanning_alert_created"
+    ),
+    sample!(
+        "dependabot_alert__created.md",
+        "dependabot_alert",
+        "dependabot_alert_created"
+    ),
+    sample!(
+        "dependabot_alert__dismissed.md",
+        "dependabot_alert",
+        "dependabot_alert_created"
+    ),
+    sample!(
+        "dependabot_alert__fixed.md",
+        "dependabot_alert",
+        "dependabot_alert_created"
+    ),
+    sample!(
+        "dependabot_alert__reopened.md",
+        "dependabot_alert",
+        "dependabot_alert_created"
+    ),
+    sample!(
+        "discussion__answered.md",
+        "discussion",
+        "discussion_answered"
+    ),
+    sample!(
+        "discussion__category_changed.md",
+        "discussion",
+        "discussion_category_changed"
+    ),
+    sample!("discussion__closed.md", "discussion", "discussion_closed"),
+    sample!("discussion__created.md", "discussion", "discussion_created"),
+    sample!(
+        "discussion_comment__created.md",
+        "discussion_comment",
+        "discussion_comment_created"
+    ),
+    sample!(
+        "issue_comment__created.md",
+        "issue_comment",
+        "issue_comment_created"
+    ),
+    sample!("issues__assigned.md", "issues", "issue_assigned"),
+    sample!("issues__closed.md", "issues", "issue_closed"),
+    sample!("issues__edited.md", "issues", "issue_edited"),
+    sample!("issues__labeled.md", "issues", "issue_labeled"),
+    sample!("issues__labels_changed.md", "issues", "issue_labeled"),
+    sample!("issues__milestoned.md", "issues", "issue_milestoned"),
+    sample!("issues__opened.md", "issues", "issue_opened"),
+    sample!("issues__reopened.md", "issues", "issue_opened"),
+    sample!("issues__transferred.md", "issues", "issue_transferred"),
+    sample!("issues__unassigned.md", "issues", "issue_unassigned"),
+    sample!("issues__unlabeled.md", "issues", "issue_unlabeled"),
+    sample!("ping.md", "ping", "ping"),
+    sample!("push.md", "push", "push"),
+    sample!("push.md", "push", "push_forced"),
+    sample!("release__prereleased.md", "release", "release_prereleased"),
+    sample!("release__published.md", "release", "release_released"),
+    sample!("release__released.md", "release", "release_released"),
+    sample!(
+        "release__released.md",
+        "release",
+        "release_released_with_assets"
+    ),
+    sample!(
+        "repository_vulnerability_alert__create.md",
+        "repository_vulnerability_alert",
+        "repository_vulnerability_alert_create"
+    ),
+    sample!(
+        "repository_vulnerability_alert__dismiss.md",
+        "repository_vulnerability_alert",
+        "repository_vulnerability_alert_create"
+    ),
+    sample!(
+        "repository_vulnerability_alert__resolve.md",
+        "repository_vulnerability_alert",
+        "repository_vulnerability_alert_create"
+    ),
+    sample!(
+        "secret_scanning_alert__created.md",
+        "secret_scanning_alert",
+        "secret_scanning_alert_created"
+    ),
+    sample!(
+        "secret_scanning_alert__reopened.md",
+        "secret_scanning_alert",
+        "secret_scanning_alert_created"
+    ),
+    sample!(
+        "secret_scanning_alert__resolved.md",
+        "secret_scanning_alert",
+        "secret_scanning_alert_created"
+    ),
+    sample!(
+        "workflow_run__completed.md",
+        "workflow_run",
+        "workflow_run_completed_failure"
+    ),
+    sample!(
+        "workflow_run__completed.md",
+        "workflow_run",
+        "workflow_run_completed_success"
+    ),
+];
@@ -0,0 +1,299 @@
+use std::time::Duration;
+
+use prose_xmpp::BareJid;
+
+use crate::commands::Permission;
+
+/// A command users send to the bot, e.g. `!status`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Help,
+    Status,
+    /// Mutes `event_types` (or all events if empty) of `repo` or of the room the command is sent
+    /// in for `duration`.
+    Mute {
+        repo: Option<String>,
+        event_types: Vec<String>,
+        duration: Duration,
+    },
+    Unmute {
+        repo: Option<String>,
+    },
+    /// Posts the events of `repo` in `room`, or in the room the command is sent in.
+    Subscribe {
+        repo: String,
+        room: Option<BareJid>,
+    },
+    Unsubscribe {
+        repo: String,
+    },
+}
+
+/// The usage and description of all commands, as listed by `!help`.
+pub const COMMANDS: &[(&str, &str)] = &[
+    ("!help", "Lists the available commands."),
+    (
+        "!status",
+        "Lists the repositories whose events are posted in this room.",
+    ),
+    (
+        MUTE_USAGE,
+        "Suppresses all or the given events of this room or a repository for a while, e.g. \
+        `!mute push 2h`.",
+    ),
+    (
+        UNMUTE_USAGE,
+        "Lifts a mute and summarizes the events suppressed in the meantime.",
+    ),
+    (
+        SUBSCRIBE_USAGE,
+        "Posts the events of a repository in this or the given room.",
+    ),
+    (
+        UNSUBSCRIBE_USAGE,
+        "Stops posting the events of a repository subscribed to with `!subscribe`.",
+    ),
+];
+
+const MUTE_USAGE: &str = "!mute [owner/repo] [event …] <duration>";
+const UNMUTE_USAGE: &str = "!unmute [owner/repo]";
+const SUBSCRIBE_USAGE: &str = "!subscribe <owner/repo> [room@server]";
+const UNSUBSCRIBE_USAGE: &str = "!unsubscribe <owner/repo>";
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum ParseError {
+    #[error("Unknown command `!{0}`. Send `!help` for a list of commands.")]
+    UnknownCommand(String),
+    #[error("Usage: `{0}`")]
+    InvalidArguments(&'static str),
+}
+
+impl Command {
+    /// Parses `body` into a command. Returns `None` if `body` isn't meant to be a command, i.e.
+    /// doesn't start with `!`.
+    pub fn parse(body: &str) -> Option<Result<Self, ParseError>> {
+        let mut words = body.trim().strip_prefix('!')?.split_whitespace();
+        let name = words.next()?;
+        let args = words.collect::<Vec<_>>();
+
+        let command = match (name, args.as_slice()) {
+            ("help", []) => Ok(Command::Help),
+            ("help", _) => Err(ParseError::InvalidArguments("!help")),
+            ("status", []) => Ok(Command::Status),
+            ("status", _) => Err(ParseError::InvalidArguments("!status")),
+            ("mute", [args @ .., duration]) => {
+                let (repo, event_types) = split_repo(args);
+                parse_duration(duration)
+                    .map(|duration| Command::Mute {
+                        repo,
+                        event_types: event_types
+                            .iter()
+                            .flat_map(|event_types| event_types.split(','))
+                            .filter(|event_type| !event_type.is_empty())
+                            .map(ToString::to_string)
+                            .collect(),
+                        duration,
+                    })
+                    .ok_or(ParseError::InvalidArguments(MUTE_USAGE))
+            }
+            ("unmute", args) => match split_repo(args) {
+                (repo, []) => Ok(Command::Unmute { repo }),
+                _ => Err(ParseError::InvalidArguments(UNMUTE_USAGE)),
+            },
+            ("mute", _) => Err(ParseError::InvalidArguments(MUTE_USAGE)),
+            ("subscribe", args) => match split_repo(args) {
+                (Some(repo), []) => Ok(Command::Subscribe { repo, room: None }),
+                (Some(repo), [room]) => room
+                    .parse()
+                    .map(|room| Command::Subscribe {
+                        repo,
+                        room: Some(room),
+                    })
+                    .map_err(|_| ParseError::InvalidArguments(SUBSCRIBE_USAGE)),
+                _ => Err(ParseError::InvalidArguments(SUBSCRIBE_USAGE)),
+            },
+            ("unsubscribe", args) => match split_repo(args) {
+                (Some(repo), []) => Ok(Command::Unsubscribe { repo }),
+                _ => Err(ParseError::InvalidArguments(UNSUBSCRIBE_USAGE)),
+            },
+            (name, _) => Err(ParseError::UnknownCommand(name.to_string())),
+        };
+
+        Some(command)
+    }
+
+    /// The permission required to run the command.
+    pub fn permission(&self) -> Permission {
+        match self {
+            Command::Help => Permission::Anyone,
+            Command::Status => Permission::Member,
+            Command::Mute { .. } | Command::Unmute { .. } => Permission::Member,
+            Command::Subscribe { .. } | Command::Unsubscribe { .. } => Permission::GlobalAdmin,
+        }
+    }
+}
+
+/// Splits off the leading repository, e.g. "owner/repo", from `args`.
+fn split_repo<'a>(args: &'a [&'a str]) -> (Option<String>, &'a [&'a str]) {
+    match args {
+        [repo, rest @ ..] if repo.contains('/') => (Some(repo.to_string()), rest),
+        _ => (None, args),
+    }
+}
+
+/// Parses durations like "90s", "30m", "2h", "1d" or "1h30m".
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let mut total = 0;
+    let mut number = String::new();
+
+    for char in value.chars() {
+        if char.is_ascii_digit() {
+            number.push(char);
+            continue;
+        }
+
+        let unit = match char {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => return None,
+        };
+        total = number
+            .parse::<u64>()
+            .ok()?
+            .checked_mul(unit)
+            .and_then(|seconds| seconds.checked_add(total))?;
+        number.clear();
+    }
+
+    if !number.is_empty() || total == 0 {
+        return None;
+    }
+
+    Some(Duration::from_secs(total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ignores_regular_messages() {
+        assert_eq!(Command::parse("Hello!"), None);
+        assert_eq!(Command::parse("!"), None);
+        assert_eq!(Command::parse(""), None);
+    }
+
+    #[test]
+    fn test_parses_commands() {
+        assert_eq!(Command::parse("!help"), Some(Ok(Command::Help)));
+        assert_eq!(Command::parse("  !status \n"), Some(Ok(Command::Status)));
+    }
+
+    #[test]
+    fn test_parses_mute() {
+        assert_eq!(
+            Command::parse("!mute 2h"),
+            Some(Ok(Command::Mute {
+                repo: None,
+                event_types: vec![],
+                duration: Duration::from_secs(7200),
+            }))
+        );
+        assert_eq!(
+            Command::parse("!mute owner/repo push,issues workflow_run 1h30m"),
+            Some(Ok(Command::Mute {
+                repo: Some("owner/repo".to_string()),
+                event_types: vec![
+                    "push".to_string(),
+                    "issues".to_string(),
+                    "workflow_run".to_string()
+                ],
+                duration: Duration::from_secs(5400),
+            }))
+        );
+        assert_eq!(
+            Command::parse("!mute push"),
+            Some(Err(ParseError::InvalidArguments(MUTE_USAGE)))
+        );
+        assert_eq!(
+            Command::parse("!mute"),
+            Some(Err(ParseError::InvalidArguments(MUTE_USAGE)))
+        );
+    }
+
+    #[test]
+    fn test_parses_unmute() {
+        assert_eq!(
+            Command::parse("!unmute"),
+            Some(Ok(Command::Unmute { repo: None }))
+        );
+        assert_eq!(
+            Command::parse("!unmute owner/repo"),
+            Some(Ok(Command::Unmute {
+                repo: Some("owner/repo".to_string())
+            }))
+        );
+        assert_eq!(
+            Command::parse("!unmute push"),
+            Some(Err(ParseError::InvalidArguments(UNMUTE_USAGE)))
+        );
+    }
+
+    #[test]
+    fn test_parses_subscribe() {
+        assert_eq!(
+            Command::parse("!subscribe owner/repo"),
+            Some(Ok(Command::Subscribe {
+                repo: "owner/repo".to_string(),
+                room: None,
+            }))
+        );
+        assert_eq!(
+            Command::parse("!subscribe owner/repo room@example.org"),
+            Some(Ok(Command::Subscribe {
+                repo: "owner/repo".to_string(),
+                room: Some("room@example.org".parse().unwrap()),
+            }))
+        );
+        assert_eq!(
+            Command::parse("!subscribe room@example.org"),
+            Some(Err(ParseError::InvalidArguments(SUBSCRIBE_USAGE)))
+        );
+        assert_eq!(
+            Command::parse("!unsubscribe owner/repo"),
+            Some(Ok(Command::Unsubscribe {
+                repo: "owner/repo".to_string()
+            }))
+        );
+        assert_eq!(
+            Command::parse("!unsubscribe"),
+            Some(Err(ParseError::InvalidArguments(UNSUBSCRIBE_USAGE)))
+        );
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("45s"), Some(Duration::from_secs(45)));
+        assert_eq!(parse_duration("1d2h"), Some(Duration::from_secs(93600)));
+        assert_eq!(parse_duration("2"), None);
+        assert_eq!(parse_duration("0m"), None);
+        assert_eq!(parse_duration("2w"), None);
+        assert_eq!(parse_duration("h"), None);
+        assert_eq!(parse_duration("999999999999999999999d"), None);
+        assert_eq!(parse_duration("9999999999999999d"), None);
+    }
+
+    #[test]
+    fn test_rejects_unknown_commands_and_arguments() {
+        assert_eq!(
+            Command::parse("!deploy now"),
+            Some(Err(ParseError::UnknownCommand("deploy".to_string())))
+        );
+        assert_eq!(
+            Command::parse("!status please"),
+            Some(Err(ParseError::InvalidArguments("!status")))
+        );
+    }
+}
@@ -0,0 +1,232 @@
+use std::time::Duration;
+
+use prose_xmpp::BareJid;
+
+use crate::config::RepoSettings;
+use crate::services::xmpp_service::{Affiliation, IncomingMessage, MessageHandler, RoomId};
+use crate::webhook::{MuteTarget, MutesStore, RepoMapping};
+
+pub use command::{parse_duration, Command, ParseError, COMMANDS};
+
+mod command;
+
+/// Who may run a command. Each level includes the ones before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Permission {
+    Anyone,
+    /// Members of the room the command is sent in.
+    Member,
+    /// The configured admins and the owners and admins of the room the command is sent in.
+    Admin,
+    /// The configured admins only. Required by commands that affect other rooms.
+    GlobalAdmin,
+}
+
+/// Runs the commands users send to the bot in rooms or directly.
+pub struct Commands {
+    admins: Vec<BareJid>,
+    mapping: RepoMapping,
+    mutes: MutesStore,
+}
+
+impl Commands {
+    pub fn new(admins: Vec<BareJid>, mapping: RepoMapping, mutes: MutesStore) -> Self {
+        Self {
+            admins,
+            mapping,
+            mutes,
+        }
+    }
+
+    fn permission(&self, message: &IncomingMessage) -> Permission {
+        if let Some(sender) = &message.sender {
+            if self.admins.contains(sender) {
+                return Permission::GlobalAdmin;
+            }
+        }
+
+        match message.affiliation {
+            Some(Affiliation::Owner | Affiliation::Admin) => Permission::Admin,
+            Some(Affiliation::Member) => Permission::Member,
+            _ => Permission::Anyone,
+        }
+    }
+
+    fn run(&self, command: Command, message: &IncomingMessage) -> String {
+        match command {
+            Command::Help => help(),
+            Command::Status => self.status(&message.room),
+            Command::Mute {
+                repo,
+                event_types,
+                duration,
+            } => self.mute(&message.room, repo, event_types, duration),
+            Command::Unmute { repo } => self.unmute(&message.room, repo),
+            Command::Subscribe { repo, room } => self.subscribe(&message.room, repo, room),
+            Command::Unsubscribe { repo } => self.unsubscribe(&repo),
+        }
+    }
+
+    fn status(&self, room: &RoomId) -> String {
+        let mut repos = self
+            .mapping
+            .all()
+            .into_iter()
+            .filter(|settings| is_reported_in(settings, room))
+            .map(|settings| format!("- {}", settings.repo))
+            .collect::<Vec<_>>();
+        repos.sort();
+
+        if repos.is_empty() {
+            return "No repositories are reported here.".to_string();
+        }
+
+        format!(
+            "Events of these repositories are posted here:\n{}",
+            repos.join("\n")
+        )
+    }
+
+    fn mute(
+        &self,
+        room: &RoomId,
+        repo: Option<String>,
+        event_types: Vec<String>,
+        duration: Duration,
+    ) -> String {
+        let target = match self.mute_target(room, repo) {
+            Ok(target) => target,
+            Err(err) => return err,
+        };
+
+        let events = if event_types.is_empty() {
+            "all events".to_string()
+        } else {
+            let event_types = event_types
+                .iter()
+                .map(|event_type| format!("`{event_type}`"))
+                .collect::<Vec<_>>();
+            format!("{} events", event_types.join(", "))
+        };
+        let of = match &target {
+            MuteTarget::Room(_) => "here".to_string(),
+            MuteTarget::Repo(repo) => format!("of `{repo}`"),
+        };
+
+        match self.mutes.mute(target, event_types, duration) {
+            Ok(mute) => format!(
+                "Muted {events} {of} until {} UTC.",
+                mute.until.format("%Y-%m-%d %H:%M")
+            ),
+            Err(err) => format!("Failed to mute: {err}"),
+        }
+    }
+
+    fn unmute(&self, room: &RoomId, repo: Option<String>) -> String {
+        let target = match self.mute_target(room, repo) {
+            Ok(target) => target,
+            Err(err) => return err,
+        };
+
+        match self.mutes.unmute(&target) {
+            Some(mute) => mute.summary(),
+            None => "Nothing is muted here.".to_string(),
+        }
+    }
+
+    fn subscribe(&self, room: &RoomId, repo: String, target: Option<BareJid>) -> String {
+        let target = match (target, room) {
+            (Some(target), _) => target,
+            (None, RoomId::Room(room)) => room.clone(),
+            (None, RoomId::User(_)) => {
+                return "Name the room, e.g. `!subscribe owner/repo room@example.org`.".to_string()
+            }
+        };
+
+        let settings = RepoSettings {
+            repo,
+            room: target,
+            security_room: None,
+            announce_ping: false,
+            announce_published_releases: false,
+            fallback_template: None,
+            locale: None,
+        };
+        let reply = format!(
+            "Events of `{}` are now posted in {}.",
+            settings.repo, settings.room
+        );
+
+        match self.mapping.subscribe(settings) {
+            Ok(()) => reply,
+            Err(err) => err.to_string(),
+        }
+    }
+
+    fn unsubscribe(&self, repo: &str) -> String {
+        match self.mapping.unsubscribe(repo) {
+            Ok(settings) => format!(
+                "Events of `{}` are no longer posted in {}.",
+                settings.repo, settings.room
+            ),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    /// Returns what a mute in `room` applies to: `repo` if given, and `room` itself otherwise.
+    /// Only repositories reported in `room` can be muted from there.
+    fn mute_target(&self, room: &RoomId, repo: Option<String>) -> Result<MuteTarget, String> {
+        match (repo, room) {
+            (Some(repo), room) => {
+                if !self
+                    .mapping
+                    .get(&repo)
+                    .is_some_and(|settings| is_reported_in(&settings, room))
+                {
+                    return Err(format!("`{repo}` isn't reported here."));
+                }
+                Ok(MuteTarget::Repo(repo))
+            }
+            (None, RoomId::Room(room)) => Ok(MuteTarget::Room(room.clone())),
+            (None, RoomId::User(_)) => {
+                Err("Name the repository, e.g. `!mute owner/repo 2h`.".to_string())
+            }
+        }
+    }
+}
+
+impl MessageHandler for Commands {
+    fn handle_message(&self, message: IncomingMessage) -> Option<String> {
+        let command = match Command::parse(&message.body)? {
+            Ok(command) => command,
+            // Rooms may be shared with other bots whose commands start with `!` as well.
+            Err(ParseError::UnknownCommand(_)) if !message.is_addressed => return None,
+            Err(err) => return Some(err.to_string()),
+        };
+
+        if self.permission(&message) < command.permission() {
+            return Some("You are not allowed to run this command.".to_string());
+        }
+
+        Some(self.run(command, &message))
+    }
+}
+
+/// Returns `true` if events of the repository are posted in `room`. Direct messages are sent by
+/// admins, who have access to all repositories.
+fn is_reported_in(settings: &RepoSettings, room: &RoomId) -> bool {
+    match room {
+        RoomId::User(_) => true,
+        RoomId::Room(room) => {
+            &settings.room == room || settings.security_room.as_ref() == Some(room)
+        }
+    }
+}
+
+fn help() -> String {
+    let commands = COMMANDS
+        .iter()
+        .map(|(usage, description)| format!("{usage} – {description}"))
+        .collect::<Vec<_>>();
+    format!("Available commands:\n{}", commands.join("\n"))
+}
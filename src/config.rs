@@ -1,7 +1,9 @@
+use std::path::PathBuf;
+
 use anyhow::format_err;
 use config::{Config, ConfigError, File};
 use prose_xmpp::BareJid;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_aux::field_attributes::deserialize_number_from_string;
 
 #[derive(Deserialize, Clone, Debug)]
@@ -9,6 +11,8 @@ pub struct Settings {
     pub app: AppSettings,
     pub xmpp: XMPPSettings,
     pub webhook: WebhookSettings,
+    /// Enables the admin pages, e.g. `/admin/preview`, if set.
+    pub admin: Option<AdminSettings>,
 }
 
 #[derive(Deserialize, Clone, Debug)]
@@ -17,23 +21,131 @@ pub struct AppSettings {
     pub port: u16,
     pub host: String,
     pub base_url: String,
+    /// Templates in this directory override the built-in templates.
+    pub templates_dir: Option<PathBuf>,
+    /// Runtime state, e.g. mutes, is persisted in this directory. It is kept in memory only if
+    /// this is not set.
+    pub data_dir: Option<PathBuf>,
+}
+
+/// The credentials required to access the admin pages via HTTP Basic authentication.
+#[derive(Deserialize, Clone, Debug)]
+pub struct AdminSettings {
+    pub username: String,
+    pub password: String,
 }
 
 #[derive(Deserialize, Clone, Debug)]
 pub struct XMPPSettings {
     pub jid: BareJid,
     pub password: String,
+    /// The resource the bot connects with.
+    #[serde(default = "default_resource")]
+    pub resource: String,
+    /// The nickname of the bot in rooms without a nickname in `rooms`.
+    #[serde(default = "default_nickname")]
+    pub nickname: String,
+    /// The status text of the bot's presence, e.g. "Watching 12 repos".
+    pub status: Option<String>,
+    /// A PNG, JPEG, GIF or WebP image published as the bot's avatar (XEP-0084) on connect.
+    pub avatar: Option<PathBuf>,
+    /// The maximum size of a message body in bytes. Longer messages are shortened where templates
+    /// allow it and split into multiple messages otherwise. `0` disables the limit.
+    #[serde(default, deserialize_with = "deserialize_number_from_string")]
+    pub max_message_size: usize,
+    /// Users allowed to run all commands, in addition to the owners and admins of a room.
+    #[serde(default)]
+    pub admins: Vec<BareJid>,
+    /// Settings of rooms that need more than their JID to be entered.
+    #[serde(default)]
+    pub rooms: Vec<RoomSettings>,
+    /// Enters the rooms `admins` invite the bot to. Direct invitations (XEP-0249) are accepted
+    /// from anywhere, mediated invitations (XEP-0045) only from rooms on the MUC services of the
+    /// rooms the bot is in already, since the inviter is named by the room. Rooms entered this
+    /// way can be left via the admin API.
+    #[serde(default)]
+    pub accept_invites: bool,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct RoomSettings {
+    pub room: BareJid,
+    /// The password of password-protected rooms.
+    pub password: Option<String>,
+    /// The nickname of the bot in the room. Defaults to `XMPPSettings::nickname`.
+    pub nickname: Option<String>,
+}
+
+impl XMPPSettings {
+    /// Checks that the resource and nicknames can be used in JIDs, so that invalid ones are
+    /// reported on startup rather than when joining rooms or sending messages.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |kind: &str, value: &str, err: &dyn std::fmt::Display| {
+            ConfigError::Message(format!("`{value}` is not a valid {kind}: {err}"))
+        };
+
+        self.jid
+            .with_resource_str(&self.resource)
+            .map_err(|err| invalid("resource", &self.resource, &err))?;
+        self.jid
+            .with_resource_str(&self.nickname)
+            .map_err(|err| invalid("nickname", &self.nickname, &err))?;
+
+        for settings in &self.rooms {
+            if let Some(nickname) = &settings.nickname {
+                settings
+                    .room
+                    .with_resource_str(nickname)
+                    .map_err(|err| invalid("nickname", nickname, &err))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn default_resource() -> String {
+    "bot".to_string()
+}
+
+fn default_nickname() -> String {
+    "bot".to_string()
 }
 
 #[derive(Deserialize, Clone, Debug)]
 pub struct WebhookSettings {
     pub repos: Vec<RepoSettings>,
+    /// Label changes on the same issue within this many seconds are reported in a single
+    /// message. `0` reports every change immediately.
+    #[serde(default, deserialize_with = "deserialize_number_from_string")]
+    pub label_changes_delay_secs: u64,
+    /// The message reporting a failed workflow run is corrected in place (XEP-0308) when the
+    /// workflow recovers or fails again within this many seconds. Afterwards a new message is
+    /// sent. `0` always sends a new message.
+    #[serde(default, deserialize_with = "deserialize_number_from_string")]
+    pub workflow_correction_max_age_secs: u64,
 }
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct RepoSettings {
     pub repo: String,
     pub room: BareJid,
+    pub security_room: Option<BareJid>,
+    /// Posts a message to `room` when GitHub pings a newly configured webhook.
+    #[serde(default)]
+    pub announce_ping: bool,
+    /// Announces releases with `release__published.md` when they are published, instead of with
+    /// `release__released.md` or `release__prereleased.md`. GitHub sends both for each release, so
+    /// only one of them is rendered. Note that `released` is also sent when a prerelease becomes
+    /// a release later on, which isn't announced then.
+    #[serde(default)]
+    pub announce_published_releases: bool,
+    /// The template used for events without a specific template, e.g. `_default.md`. Events
+    /// without a template are dropped if this is not set.
+    pub fallback_template: Option<String>,
+    /// The language of the rooms, e.g. "fr". Localized templates like `push.fr.md.j2` are used
+    /// if they exist and dates and plurals are formatted accordingly.
+    pub locale: Option<String>,
 }
 
 pub enum Environment {
@@ -42,8 +154,13 @@ pub enum Environment {
 }
 
 pub fn get_configuration() -> Result<Settings, ConfigError> {
-    let base_path = std::env::current_dir().expect("Failed to determine the current directory");
-    let configuration_directory = base_path.join("config");
+    // Allow running the binary from anywhere by pointing APP_CONFIG_DIR to the configuration.
+    let configuration_directory = match std::env::var_os("APP_CONFIG_DIR") {
+        Some(directory) => PathBuf::from(directory),
+        None => std::env::current_dir()
+            .expect("Failed to determine the current directory")
+            .join("config"),
+    };
 
     let environment: Environment = std::env::var("APP_ENVIRONMENT")
         .unwrap_or_else(|_| "local".into())
@@ -67,7 +184,9 @@ pub fn get_configuration() -> Result<Settings, ConfigError> {
         )
         .build()?;
 
-    settings.try_deserialize()
+    let settings: Settings = settings.try_deserialize()?;
+    settings.xmpp.validate()?;
+    Ok(settings)
 }
 
 impl Environment {
@@ -94,3 +213,26 @@ impl TryFrom<String> for Environment {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn xmpp_settings(nickname: &str, room_nickname: Option<&str>) -> XMPPSettings {
+        serde_json::from_value(serde_json::json!({
+            "jid": "bot@example.org",
+            "password": "secret",
+            "nickname": nickname,
+            "rooms": [{ "room": "room@example.org", "nickname": room_nickname }],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_invalid_nicknames_are_rejected() {
+        assert!(xmpp_settings("GitHub", None).validate().is_ok());
+        assert!(xmpp_settings("GitHub", Some("Bot")).validate().is_ok());
+        assert!(xmpp_settings("", None).validate().is_err());
+        assert!(xmpp_settings("GitHub", Some("")).validate().is_err());
+    }
+}
@@ -1,4 +1,6 @@
 pub mod app;
+pub mod cli;
+pub mod commands;
 pub mod config;
 pub mod routes;
 pub mod services;
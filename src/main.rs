@@ -1,25 +1,45 @@
 use anyhow::Result;
+use clap::Parser;
 
 use xmpp_webhook::app::App;
+use xmpp_webhook::cli::{self, Cli, Command};
 use xmpp_webhook::config::get_configuration;
 use xmpp_webhook::services::xmpp_handle::XMPPHandle;
 use xmpp_webhook::telemetry::{build_subscriber, init_subscriber};
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    match cli.command {
+        Some(Command::Render {
+            event,
+            fixture,
+            repo,
+        }) => {
+            // Keep stdout free for the rendered messages.
+            init_subscriber(build_subscriber("xmpp-webhook", "warn", std::io::stderr));
+            let config = get_configuration().expect("Failed to read configuration");
+            return cli::render(config, &event, &fixture, repo.as_deref());
+        }
+        Some(Command::CheckTemplates { templates_dir }) => {
+            init_subscriber(build_subscriber("xmpp-webhook", "warn", std::io::stderr));
+            let templates_dir = templates_dir.or_else(|| {
+                get_configuration()
+                    .expect("Failed to read configuration")
+                    .app
+                    .templates_dir
+            });
+            return cli::check_templates(templates_dir);
+        }
+        None => (),
+    }
+
     init_subscriber(build_subscriber("xmpp-webhook", "info", std::io::stdout));
 
     let config = get_configuration().expect("Failed to read configuration");
 
-    let xmpp_handle = XMPPHandle::new(
-        config.xmpp.clone(),
-        config
-            .webhook
-            .repos
-            .iter()
-            .map(|setting| setting.room.clone())
-            .collect(),
-    );
+    let xmpp_handle = XMPPHandle::new(config.xmpp.clone());
 
     let app = App::build(config, xmpp_handle).await?;
     app.run_until_stopped().await?;
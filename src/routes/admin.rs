@@ -0,0 +1,349 @@
+use std::collections::BTreeMap;
+use std::future::{ready, Ready};
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::dev::Payload;
+use actix_web::http::header::{self, ContentType};
+use actix_web::http::StatusCode;
+use actix_web::{web, FromRequest, HttpRequest, HttpResponse, ResponseError};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use minijinja::{context, Environment};
+use prose_xmpp::BareJid;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use subtle::ConstantTimeEq;
+
+use crate::cli::samples::SAMPLES;
+use crate::config::{AdminSettings, RepoSettings};
+use crate::services::xmpp_handle::message_styling_fallback;
+use crate::services::xmpp_service::{DeliveryStatus, OutgoingMessage, RoomId};
+use crate::services::XMPPService;
+use crate::templates::{split_locale, Templates};
+use crate::webhook::{MuteTarget, MutesStore, RepoMapping, SubscriptionError};
+
+#[derive(thiserror::Error, Debug)]
+pub enum AdminError {
+    #[error("Admin pages are disabled")]
+    Disabled,
+    #[error("Unauthorized")]
+    Unauthorized,
+    #[error("Not found")]
+    NotFound,
+    #[error("{0}")]
+    InvalidRequest(String),
+    #[error(transparent)]
+    RenderingError(#[from] minijinja::Error),
+    #[error(transparent)]
+    SubscriptionError(#[from] SubscriptionError),
+}
+
+impl ResponseError for AdminError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AdminError::Disabled => StatusCode::NOT_FOUND,
+            AdminError::Unauthorized => StatusCode::UNAUTHORIZED,
+            AdminError::NotFound => StatusCode::NOT_FOUND,
+            AdminError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            AdminError::RenderingError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AdminError::SubscriptionError(SubscriptionError::Configured(_)) => StatusCode::CONFLICT,
+            AdminError::SubscriptionError(SubscriptionError::NotSubscribed(_)) => {
+                StatusCode::NOT_FOUND
+            }
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if let AdminError::Unauthorized = self {
+            response.insert_header((header::WWW_AUTHENTICATE, r#"Basic realm="admin""#));
+        }
+        response.body(self.to_string())
+    }
+}
+
+/// Extracting `Admin` fails unless the request carries the credentials from `AdminSettings` via
+/// HTTP Basic authentication. Admin pages are disabled if `AdminSettings` aren't configured.
+pub struct Admin;
+
+impl FromRequest for Admin {
+    type Error = AdminError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let Some(settings) = req.app_data::<web::Data<AdminSettings>>() else {
+            return ready(Err(AdminError::Disabled));
+        };
+
+        let credentials = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Basic "))
+            .and_then(|value| BASE64_STANDARD.decode(value.trim()).ok())
+            .and_then(|value| String::from_utf8(value).ok());
+
+        let is_authorized = credentials
+            .as_deref()
+            .and_then(|credentials| credentials.split_once(':'))
+            .is_some_and(|(username, password)| {
+                // Compares in constant time so the response time doesn't reveal how much of the
+                // credentials was guessed right. Both are compared to not reveal which was wrong.
+                let username = username.as_bytes().ct_eq(settings.username.as_bytes());
+                let password = password.as_bytes().ct_eq(settings.password.as_bytes());
+                (username & password).into()
+            });
+
+        ready(if is_authorized {
+            Ok(Admin)
+        } else {
+            Err(AdminError::Unauthorized)
+        })
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct PreviewForm {
+    /// The name of the template to render, e.g. `push.md` or `owner/repo/push.fr.md`.
+    template: String,
+    /// The name of a bundled sample payload, see `cli::samples`.
+    sample: String,
+    /// A pasted payload. Takes precedence over `sample`.
+    payload: String,
+}
+
+/// Shows a form to render a template against a sample or pasted payload.
+pub async fn preview(
+    _admin: Admin,
+    templates: web::Data<Templates>,
+) -> Result<HttpResponse, AdminError> {
+    render_page(&templates, &PreviewForm::default(), None)
+}
+
+/// Renders the template chosen in the form and shows the resulting message along with the
+/// XEP-0393 message styling fallback sent to clients that don't render markdown.
+pub async fn render_preview(
+    _admin: Admin,
+    templates: web::Data<Templates>,
+    form: web::Form<PreviewForm>,
+) -> Result<HttpResponse, AdminError> {
+    let result = render_template(&templates, &form);
+    render_page(&templates, &form, Some(result))
+}
+
+fn render_template(templates: &Templates, form: &PreviewForm) -> Result<String, String> {
+    let environment = templates.environment();
+    let template = environment
+        .get_template(&form.template)
+        .map_err(|err| err.to_string())?;
+
+    let sample = SAMPLES.iter().find(|sample| sample.name == form.sample);
+    let payload = if !form.payload.trim().is_empty() {
+        form.payload.as_str()
+    } else if let Some(sample) = sample {
+        sample.payload
+    } else {
+        return Err("Select a sample or paste a payload.".to_string());
+    };
+    let event =
+        serde_json::from_str::<Value>(payload).map_err(|err| format!("Invalid payload: {err}"))?;
+
+    let (base_name, locale) = split_locale(form.template.rsplit('/').next().unwrap_or_default());
+    let event_type = match sample {
+        Some(sample) => sample.event_type,
+        None => base_name
+            .split_once("__")
+            .map(|(event_type, _)| event_type)
+            .or_else(|| base_name.strip_suffix(".md"))
+            .unwrap_or(&base_name),
+    };
+
+    // The union of the context of all call sites, see `routes::webhook`.
+    templates
+        .render(
+            &template,
+            context!(
+                event => event,
+                event_type => event_type,
+                added => ["enhancement"],
+                removed => ["bug"],
+                unsupported_events => Vec::<&str>::new(),
+                locale => locale,
+            ),
+        )
+        .map_err(|err| format!("{err}\n{}", err.display_debug_info()))
+}
+
+fn render_page(
+    templates: &Templates,
+    form: &PreviewForm,
+    result: Option<Result<String, String>>,
+) -> Result<HttpResponse, AdminError> {
+    let mut template_names = templates
+        .environment()
+        .templates()
+        .map(|(name, _)| name.to_string())
+        .collect::<Vec<_>>();
+    template_names.sort();
+
+    let mut sample_names = SAMPLES.iter().map(|sample| sample.name).collect::<Vec<_>>();
+    sample_names.sort();
+    sample_names.dedup();
+
+    let (markdown, error) = match result {
+        Some(Ok(markdown)) => (Some(markdown), None),
+        Some(Err(error)) => (None, Some(error)),
+        None => (None, None),
+    };
+    let fallback = markdown.as_deref().map(message_styling_fallback);
+
+    let mut environment = Environment::new();
+    environment.add_template("preview.html", include_str!("admin_preview.html"))?;
+    let page = environment.get_template("preview.html")?.render(context!(
+        templates => template_names,
+        samples => sample_names,
+        template => &form.template,
+        sample => &form.sample,
+        payload => &form.payload,
+        markdown => markdown,
+        fallback => fallback,
+        error => error,
+    ))?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(page))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MuteRequest {
+    target: MuteTarget,
+    /// The event types to mute. All events are muted if empty.
+    #[serde(default)]
+    event_types: Vec<String>,
+    duration_secs: u64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UnmuteRequest {
+    target: MuteTarget,
+}
+
+/// Lists the active mutes.
+pub async fn list_mutes(_admin: Admin, mutes: web::Data<MutesStore>) -> HttpResponse {
+    HttpResponse::Ok().json(mutes.list())
+}
+
+/// Mutes a room or repository, see `MutesStore::mute`.
+pub async fn mute(
+    _admin: Admin,
+    mutes: web::Data<MutesStore>,
+    request: web::Json<MuteRequest>,
+) -> Result<HttpResponse, AdminError> {
+    let request = request.into_inner();
+    let mute = mutes
+        .mute(
+            request.target,
+            request.event_types,
+            Duration::from_secs(request.duration_secs),
+        )
+        .map_err(|err| AdminError::InvalidRequest(err.to_string()))?;
+
+    Ok(HttpResponse::Ok().json(mute))
+}
+
+/// Lifts a mute and posts the summary of the suppressed events to the affected room.
+pub async fn unmute(
+    _admin: Admin,
+    mutes: web::Data<MutesStore>,
+    mapping: web::Data<RepoMapping>,
+    xmpp: web::Data<Arc<dyn XMPPService>>,
+    request: web::Json<UnmuteRequest>,
+) -> Result<HttpResponse, AdminError> {
+    let mute = mutes.unmute(&request.target).ok_or(AdminError::NotFound)?;
+
+    if let Some(room) = mute.room(&mapping) {
+        xmpp.send_message(OutgoingMessage::new(RoomId::Room(room), mute.summary()));
+    }
+
+    Ok(HttpResponse::Ok().json(mute))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UnsubscribeRequest {
+    repo: String,
+}
+
+/// Lists the configured and subscribed repositories.
+pub async fn list_subscriptions(_admin: Admin, mapping: web::Data<RepoMapping>) -> HttpResponse {
+    let mut repos = mapping.all();
+    repos.sort_by(|lhs, rhs| lhs.repo.cmp(&rhs.repo));
+    HttpResponse::Ok().json(repos)
+}
+
+/// Subscribes a room to the events of a repository. The bot enters the room if needed.
+pub async fn subscribe(
+    _admin: Admin,
+    mapping: web::Data<RepoMapping>,
+    request: web::Json<RepoSettings>,
+) -> Result<HttpResponse, AdminError> {
+    let settings = request.into_inner();
+    mapping.subscribe(settings.clone())?;
+    Ok(HttpResponse::Ok().json(settings))
+}
+
+/// Removes a subscription. The bot leaves rooms no events are posted in anymore.
+pub async fn unsubscribe(
+    _admin: Admin,
+    mapping: web::Data<RepoMapping>,
+    request: web::Json<UnsubscribeRequest>,
+) -> Result<HttpResponse, AdminError> {
+    let settings = mapping.unsubscribe(&request.repo)?;
+    Ok(HttpResponse::Ok().json(settings))
+}
+
+/// Lists the rooms the bot is supposed to be in and whether it is.
+pub async fn list_rooms(_admin: Admin, xmpp: web::Data<Arc<dyn XMPPService>>) -> HttpResponse {
+    HttpResponse::Ok().json(xmpp.room_states())
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LeaveRoomRequest {
+    room: BareJid,
+}
+
+/// Leaves a room the bot entered because it was invited to it.
+pub async fn leave_room(
+    _admin: Admin,
+    xmpp: web::Data<Arc<dyn XMPPService>>,
+    request: web::Json<LeaveRoomRequest>,
+) -> Result<HttpResponse, AdminError> {
+    if !xmpp.leave_room(request.into_inner().room) {
+        return Err(AdminError::NotFound);
+    }
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Lists whether the recently sent messages were confirmed, along with the number of messages by
+/// status.
+pub async fn list_deliveries(_admin: Admin, xmpp: web::Data<Arc<dyn XMPPService>>) -> HttpResponse {
+    let deliveries = xmpp.deliveries();
+
+    let mut counts = BTreeMap::<&str, usize>::new();
+    for delivery in &deliveries {
+        let status = match delivery.status {
+            DeliveryStatus::Pending => "pending",
+            DeliveryStatus::Delivered => "delivered",
+            DeliveryStatus::Unconfirmed => "unconfirmed",
+            DeliveryStatus::Rejected { .. } => "rejected",
+            DeliveryStatus::Failed { .. } => "failed",
+        };
+        *counts.entry(status).or_default() += 1;
+    }
+
+    HttpResponse::Ok().json(json!({
+        "counts": counts,
+        "deliveries": deliveries,
+    }))
+}
@@ -1,7 +1,9 @@
+pub use admin::*;
 pub use health_check::*;
 pub use home::*;
 pub use webhook::*;
 
+mod admin;
 mod health_check;
 mod home;
 mod webhook;
@@ -1,15 +1,29 @@
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 
 use actix_web::http::StatusCode;
 use actix_web::{web, HttpRequest, HttpResponse, Responder, ResponseError};
 use minijinja::{context, Environment};
-use serde_json::Value;
-use tracing::info;
+use serde_json::{json, Value};
+use tracing::{error, info, warn};
 
-use crate::services::xmpp_service::RoomId;
+use crate::config::RepoSettings;
+use crate::services::xmpp_service::{new_message_id, OutgoingMessage, RoomId};
 use crate::services::XMPPService;
-use crate::webhook::{RepoMapping, WorkflowRunsStore};
+use crate::templates::{get_template_for_repo, supports_event, Templates};
+use crate::webhook::{
+    LabelChangesStore, MutesStore, RepoMapping, Report, ThreadsStore, WorkflowRunsStore,
+};
+
+/// Events that may disclose vulnerabilities or leaked credentials. These are only ever delivered
+/// to the repository's `security_room`.
+const SECURITY_EVENTS: &[&str] = &[
+    "code_scanning_alert",
+    "dependabot_alert",
+    "repository_vulnerability_alert",
+    "secret_scanning_alert",
+];
 
 #[derive(thiserror::Error, Debug)]
 pub enum WebhookError {
@@ -34,13 +48,17 @@ impl ResponseError for WebhookError {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn webhook(
     req: HttpRequest,
     xmpp: web::Data<Arc<dyn XMPPService>>,
     body: web::Bytes,
     mapping: web::Data<RepoMapping>,
     workflow_runs: web::Data<WorkflowRunsStore>,
-    environment: web::Data<Environment<'_>>,
+    label_changes: web::Data<LabelChangesStore>,
+    threads: web::Data<ThreadsStore>,
+    mutes: web::Data<MutesStore>,
+    templates: web::Data<Templates>,
 ) -> Result<impl Responder, WebhookError> {
     let Some(event_type) = req
         .headers()
@@ -51,21 +69,73 @@ pub async fn webhook(
     };
 
     let event = serde_json::from_slice::<HashMap<String, Value>>(&body)?;
+
+    handle_event(
+        event_type,
+        event,
+        &xmpp,
+        &mapping,
+        &workflow_runs,
+        &label_changes,
+        &threads,
+        &mutes,
+        &templates,
+    )
+}
+
+/// Routes `event` to the room configured for its repository and renders it. This is everything
+/// the webhook does apart from parsing the request, so that events can be rendered offline, too.
+#[allow(clippy::too_many_arguments)]
+pub fn handle_event(
+    event_type: &str,
+    event: HashMap<String, Value>,
+    xmpp: &web::Data<Arc<dyn XMPPService>>,
+    mapping: &RepoMapping,
+    workflow_runs: &WorkflowRunsStore,
+    label_changes: &web::Data<LabelChangesStore>,
+    threads: &web::Data<ThreadsStore>,
+    mutes: &MutesStore,
+    templates: &web::Data<Templates>,
+) -> Result<HttpResponse, WebhookError> {
+    let environment = templates.environment();
+
+    if event_type == "ping" {
+        return ping(&event, mapping, templates, &environment, xmpp);
+    }
+
     let repo = event
         .get("repository")
         .and_then(|repo| repo.get("full_name"))
         .and_then(|repo| repo.as_str())
         .ok_or(WebhookError::MissingRepo)?;
 
-    let Some(jid) = mapping.get(&repo) else {
+    let Some(settings) = mapping.get(repo) else {
         return Ok(HttpResponse::Ok().body("unknown repo"));
     };
 
+    let jid = if SECURITY_EVENTS.contains(&event_type) {
+        let Some(security_room) = &settings.security_room else {
+            info!("No security room configured for {repo}. Dropping {event_type} event.");
+            return Ok(HttpResponse::Ok().body("ok"));
+        };
+        security_room
+    } else {
+        &settings.room
+    };
+
+    if mutes.suppress(jid, repo, event_type) {
+        info!("Suppressed {event_type} event of {repo} while muted.");
+        return Ok(HttpResponse::Ok().body("muted"));
+    }
+
+    let message_id = new_message_id();
+    let mut report = Report::New;
+
     match event_type {
         "workflow_run" => {
             // We want to send a message for each failed workflow run, for succeeded workflow runs
             // however we only want to send a message if we had a prior identical failed
-            // workflow run.
+            // workflow run. Both correct the message about the prior failure if possible.
 
             let workflow_id: u64 = event["workflow_run"]["workflow_id"]
                 .as_u64()
@@ -79,11 +149,17 @@ pub async fn webhook(
                 .unwrap_or_default()
             {
                 "success" => {
-                    if !workflow_runs.workflow_succeeded(repo, workflow_id, head_branch) {
+                    let Some(success_report) =
+                        workflow_runs.workflow_succeeded(repo, workflow_id, head_branch)
+                    else {
                         return Ok(HttpResponse::Ok().body("ok"));
-                    }
+                    };
+                    report = success_report;
+                }
+                "failure" => {
+                    report =
+                        workflow_runs.workflow_failed(repo, workflow_id, head_branch, &message_id)
                 }
-                "failure" => workflow_runs.workflow_failed(repo, workflow_id, head_branch),
                 "cancelled" => {
                     // Ignore cancelled workflows
                     return Ok(HttpResponse::Ok().body("ok"));
@@ -91,6 +167,61 @@ pub async fn webhook(
                 _ => {}
             }
         }
+        "issues" => {
+            // Label changes are collected for a while and then sent as a single message, so that
+            // labelling an issue right after opening it doesn't flood the room.
+            let action = event
+                .get("action")
+                .and_then(|action| action.as_str())
+                .unwrap_or_default();
+
+            if let (Some(delay), "labeled" | "unlabeled") = (label_changes.delay(), action) {
+                let issue_number = event
+                    .get("issue")
+                    .and_then(|issue| issue["number"].as_u64())
+                    .unwrap_or_default();
+                let label = event
+                    .get("label")
+                    .and_then(|label| label["name"].as_str())
+                    .unwrap_or_default();
+
+                let is_first_change = if action == "labeled" {
+                    label_changes.label_added(repo, issue_number, label, event.clone())
+                } else {
+                    label_changes.label_removed(repo, issue_number, label, event.clone())
+                };
+
+                if is_first_change {
+                    actix_web::rt::spawn(send_label_changes(
+                        delay,
+                        settings.clone(),
+                        issue_number,
+                        label_changes.clone(),
+                        threads.clone(),
+                        templates.clone(),
+                        xmpp.clone(),
+                    ));
+                }
+
+                return Ok(HttpResponse::Ok().body("ok"));
+            }
+        }
+        "release" => {
+            // GitHub sends `published` along with either `released` or `prereleased`. We only
+            // render one of them so that a release isn't announced twice.
+            let ignored_actions: &[&str] = if settings.announce_published_releases {
+                &["released", "prereleased"]
+            } else {
+                &["published"]
+            };
+            let action = event
+                .get("action")
+                .and_then(|action| action.as_str())
+                .unwrap_or_default();
+            if ignored_actions.contains(&action) {
+                return Ok(HttpResponse::Ok().body("ok"));
+            }
+        }
         "push" => {
             // Ignore pushes with empty commits, e.g. when pushing a tag.
             if event["commits"]
@@ -114,13 +245,198 @@ pub async fn webhook(
         .unwrap_or_else(|| event_type.to_string())
         + ".md";
 
-    let Some(template) = environment.get_template(&template_name).ok() else {
+    // Fall back to the generic template if configured, so that events we haven't written a
+    // template for yet are at least visible.
+    let locale = settings.locale.as_deref();
+    let template =
+        get_template_for_repo(&environment, repo, &template_name, locale).or_else(|| {
+            settings
+                .fallback_template
+                .as_deref()
+                .and_then(|fallback| get_template_for_repo(&environment, repo, fallback, locale))
+        });
+
+    let Some(template) = template else {
         info!("No template for event {template_name}");
         return Ok(HttpResponse::Ok().body("ok"));
     };
 
-    let message = template.render(context!(event => event))?;
+    let message = templates.render(
+        &template,
+        context!(event => &event, event_type => event_type, locale => locale),
+    )?;
 
-    xmpp.send_message(RoomId::Room(jid.clone()), message);
+    let message = OutgoingMessage::with_id(message_id, RoomId::Room(jid.clone()), message);
+    let message = match report {
+        Report::New => message,
+        Report::Correct(id) => message.replacing(id),
+    };
+    xmpp.send_message(thread_message(message, repo, &event, threads));
     Ok(HttpResponse::Ok().body("message sent"))
 }
+
+/// Acknowledges the `ping` event GitHub sends when a webhook is created. Reports the events the
+/// webhook is subscribed to that would be dropped for lack of a template and, if configured,
+/// announces the new webhook in the repo's room.
+fn ping(
+    event: &HashMap<String, Value>,
+    mapping: &RepoMapping,
+    templates: &Templates,
+    environment: &Environment<'static>,
+    xmpp: &Arc<dyn XMPPService>,
+) -> Result<HttpResponse, WebhookError> {
+    let repo = event
+        .get("repository")
+        .and_then(|repo| repo["full_name"].as_str());
+    let hook_events = event
+        .get("hook")
+        .and_then(|hook| hook["events"].as_array())
+        .map(|events| events.iter().filter_map(Value::as_str).collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    let settings = repo.and_then(|repo| mapping.get(repo));
+    let locale = settings
+        .as_ref()
+        .and_then(|settings| settings.locale.clone());
+
+    // Events without a template aren't dropped if the repo has a fallback template.
+    let has_fallback = settings.as_ref().is_some_and(|settings| {
+        settings
+            .fallback_template
+            .as_deref()
+            .and_then(|fallback| {
+                get_template_for_repo(
+                    environment,
+                    &settings.repo,
+                    fallback,
+                    settings.locale.as_deref(),
+                )
+            })
+            .is_some()
+    });
+
+    // "*" subscribes to all events, which we can't possibly support.
+    let unsupported_events = hook_events
+        .into_iter()
+        .filter(|event_type| {
+            !has_fallback
+                && *event_type != "*"
+                && !supports_event(
+                    environment,
+                    repo.unwrap_or_default(),
+                    event_type,
+                    locale.as_deref(),
+                )
+        })
+        .collect::<Vec<_>>();
+
+    if !unsupported_events.is_empty() {
+        warn!(
+            "Webhook for {} is subscribed to events without a template: {}",
+            repo.unwrap_or("<no repo>"),
+            unsupported_events.join(", ")
+        );
+    }
+
+    if let Some(settings) = settings.filter(|settings| settings.announce_ping) {
+        if let Some(template) =
+            get_template_for_repo(environment, &settings.repo, "ping.md", locale.as_deref())
+        {
+            let message = templates.render(
+                &template,
+                context!(
+                    event => event,
+                    unsupported_events => unsupported_events,
+                    locale => locale,
+                ),
+            )?;
+            xmpp.send_message(OutgoingMessage::new(
+                RoomId::Room(settings.room.clone()),
+                message,
+            ));
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(json!({
+        "hook_id": event.get("hook_id"),
+        "zen": event.get("zen"),
+        "unsupported_events": unsupported_events,
+    })))
+}
+
+/// Waits for `delay` and sends a single message summarizing all label changes that happened on
+/// the issue in the meantime.
+async fn send_label_changes(
+    delay: Duration,
+    settings: RepoSettings,
+    issue_number: u64,
+    label_changes: web::Data<LabelChangesStore>,
+    threads: web::Data<ThreadsStore>,
+    templates: web::Data<Templates>,
+    xmpp: web::Data<Arc<dyn XMPPService>>,
+) {
+    tokio::time::sleep(delay).await;
+
+    let repo = &settings.repo;
+    let locale = settings.locale.as_deref();
+
+    let Some(changes) = label_changes.take(repo, issue_number) else {
+        return;
+    };
+
+    let environment = templates.environment();
+    let Some(template) =
+        get_template_for_repo(&environment, repo, "issues__labels_changed.md", locale)
+    else {
+        info!("No template for event issues__labels_changed.md");
+        return;
+    };
+
+    let message = templates.render(
+        &template,
+        context!(
+            event => &changes.event,
+            added => changes.added,
+            removed => changes.removed,
+            locale => locale,
+        ),
+    );
+
+    match message {
+        Ok(message) => xmpp.send_message(thread_message(
+            OutgoingMessage::new(RoomId::Room(settings.room.clone()), message),
+            repo,
+            &changes.event,
+            &threads,
+        )),
+        Err(err) => error!("Failed to render label changes of {repo}#{issue_number}: {err}"),
+    }
+}
+
+/// Sends messages about an issue, pull request or discussion in a thread of their own. Follow-up
+/// messages reply to the first message about it.
+fn thread_message(
+    message: OutgoingMessage,
+    repo: &str,
+    event: &HashMap<String, Value>,
+    threads: &ThreadsStore,
+) -> OutgoingMessage {
+    let Some(number) = ["issue", "pull_request", "discussion"]
+        .into_iter()
+        .find_map(|subject| {
+            event
+                .get(subject)
+                .and_then(|subject| subject["number"].as_u64())
+        })
+    else {
+        return message;
+    };
+
+    let root = threads.message_sent(repo, number, &message.id);
+    let message = message.in_thread(format!("{repo}#{number}"));
+
+    match root {
+        Some(root) => message.replying_to(root),
+        None => message,
+    }
+}
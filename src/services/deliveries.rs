@@ -0,0 +1,373 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use crate::services::xmpp_service::{Delivery, DeliveryStatus, OutgoingMessage, RoomId};
+
+/// How long to wait for a receipt (XEP-0184) or the reflection of a room message before sending
+/// it again.
+const CONFIRMATION_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often a message is sent before it is considered failed.
+const MAX_ATTEMPTS: u32 = 3;
+/// The number of delivered, unconfirmed or failed messages that are kept for inspection.
+const MAX_FINISHED: usize = 500;
+
+/// Tracks whether the messages we sent were confirmed, i.e. acknowledged with a receipt
+/// (XEP-0184) in direct chats or reflected by the room in MUCs.
+#[derive(Debug, Clone)]
+pub struct Deliveries {
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+    timeout: Duration,
+    max_attempts: u32,
+}
+
+/// One of the stanzas a message was split into, see `split_message`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Part {
+    pub id: String,
+    pub body: String,
+}
+
+/// The parts of a message that need to be sent again. They keep their original ids, so that
+/// clients that already received them can tell the retries are duplicates (XEP-0359).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Retry {
+    pub message: OutgoingMessage,
+    pub parts: Vec<Part>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    message: OutgoingMessage,
+    /// The parts of the message that weren't confirmed yet, in the order they were sent.
+    pending_parts: Vec<Part>,
+    status: DeliveryStatus,
+    attempts: u32,
+    sent_at: DateTime<Utc>,
+}
+
+impl Deliveries {
+    pub fn new() -> Self {
+        Self::with_timeout(CONFIRMATION_TIMEOUT, MAX_ATTEMPTS)
+    }
+
+    pub fn with_timeout(timeout: Duration, max_attempts: u32) -> Self {
+        Self {
+            entries: Default::default(),
+            timeout,
+            max_attempts,
+        }
+    }
+
+    /// Records that `message` was sent as `parts`.
+    pub fn sent(&self, message: &OutgoingMessage, parts: Vec<Part>) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.insert(
+            message.id.clone(),
+            Entry {
+                message: message.clone(),
+                pending_parts: parts,
+                status: DeliveryStatus::Pending,
+                attempts: 1,
+                sent_at: Utc::now(),
+            },
+        );
+
+        prune(&mut entries);
+    }
+
+    /// Records that the stanza with id `stanza_id` was confirmed.
+    pub fn confirmed(&self, stanza_id: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        let Some(entry) = entries
+            .values_mut()
+            .find(|entry| entry.pending_parts.iter().any(|part| part.id == stanza_id))
+        else {
+            return;
+        };
+
+        entry.pending_parts.retain(|part| part.id != stanza_id);
+        if entry.pending_parts.is_empty() {
+            entry.status = DeliveryStatus::Delivered;
+        }
+    }
+
+    /// Records that the stanza with id `stanza_id` was rejected. It is sent again later on.
+    pub fn rejected(&self, stanza_id: &str, reason: String) {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(entry) = entries
+            .values_mut()
+            .find(|entry| entry.pending_parts.iter().any(|part| part.id == stanza_id))
+        {
+            entry.status = DeliveryStatus::Rejected { reason };
+        }
+    }
+
+    /// Records that `message` couldn't be sent at all, e.g. because the room it was queued for was
+    /// left in the meantime.
+    pub fn failed(&self, message: &OutgoingMessage, reason: String) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.insert(
+            message.id.clone(),
+            Entry {
+                message: message.clone(),
+                pending_parts: vec![],
+                status: DeliveryStatus::Failed { reason },
+                attempts: 0,
+                sent_at: Utc::now(),
+            },
+        );
+
+        prune(&mut entries);
+    }
+
+    /// Returns the parts of room messages that weren't confirmed in time and of rejected messages,
+    /// which should be sent again. Parts that were confirmed already aren't sent again. Messages
+    /// that were sent too often already are marked as failed.
+    ///
+    /// Direct messages are confirmed by the recipient's client, which may be offline. The server
+    /// keeps messages for offline users, so they are marked as unconfirmed instead of being sent
+    /// again.
+    pub fn take_unconfirmed(&self) -> Vec<Retry> {
+        let now = Utc::now();
+        let is_overdue = |entry: &Entry| {
+            entry.status == DeliveryStatus::Pending
+                && (now - entry.sent_at).to_std().unwrap_or_default() >= self.timeout
+        };
+
+        for entry in self.entries.lock().unwrap().values_mut() {
+            if matches!(entry.message.to, RoomId::User(_)) && is_overdue(entry) {
+                entry.status = DeliveryStatus::Unconfirmed;
+            }
+        }
+
+        self.take(|entry| {
+            is_overdue(entry) || matches!(entry.status, DeliveryStatus::Rejected { .. })
+        })
+    }
+
+    fn take(&self, is_due: impl Fn(&Entry) -> bool) -> Vec<Retry> {
+        let now = Utc::now();
+        let mut entries = self.entries.lock().unwrap();
+        let mut retries = vec![];
+
+        for entry in entries.values_mut() {
+            if !is_due(entry) {
+                continue;
+            }
+
+            if entry.attempts >= self.max_attempts {
+                let reason = match &entry.status {
+                    DeliveryStatus::Rejected { reason } => reason.clone(),
+                    _ => "Not confirmed".to_string(),
+                };
+                entry.status = DeliveryStatus::Failed { reason };
+                continue;
+            }
+
+            retries.push((
+                entry.sent_at,
+                Retry {
+                    message: entry.message.clone(),
+                    parts: entry.pending_parts.clone(),
+                },
+            ));
+            // Wait for the retry to be sent rather than retrying again in the meantime.
+            entry.status = DeliveryStatus::Pending;
+            entry.attempts += 1;
+            entry.sent_at = now;
+        }
+
+        retries.sort_by_key(|(sent_at, _)| *sent_at);
+        retries.into_iter().map(|(_, retry)| retry).collect()
+    }
+
+    /// Returns the tracked messages, most recent first.
+    pub fn list(&self) -> Vec<Delivery> {
+        let mut deliveries = self
+            .entries
+            .lock()
+            .unwrap()
+            .values()
+            .map(|entry| Delivery {
+                id: entry.message.id.clone(),
+                to: entry.message.to.jid().clone(),
+                status: entry.status.clone(),
+                attempts: entry.attempts,
+                sent_at: entry.sent_at,
+            })
+            .collect::<Vec<_>>();
+        deliveries.sort_by(|lhs, rhs| rhs.sent_at.cmp(&lhs.sent_at));
+        deliveries
+    }
+}
+
+/// Removes the oldest delivered, unconfirmed and failed messages beyond `MAX_FINISHED`.
+fn prune(entries: &mut HashMap<String, Entry>) {
+    let mut finished = entries
+        .iter()
+        .filter(|(_, entry)| {
+            matches!(
+                entry.status,
+                DeliveryStatus::Delivered
+                    | DeliveryStatus::Unconfirmed
+                    | DeliveryStatus::Failed { .. }
+            )
+        })
+        .map(|(id, entry)| (entry.sent_at, id.clone()))
+        .collect::<Vec<_>>();
+
+    if finished.len() <= MAX_FINISHED {
+        return;
+    }
+
+    finished.sort();
+    for (_, id) in &finished[..finished.len() - MAX_FINISHED] {
+        entries.remove(id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::services::xmpp_service::RoomId;
+
+    use super::*;
+
+    fn message(id: &str) -> OutgoingMessage {
+        OutgoingMessage::with_id(
+            id,
+            RoomId::Room("room@example.org".parse().unwrap()),
+            "Hello",
+        )
+    }
+
+    fn parts(ids: &[&str]) -> Vec<Part> {
+        ids.iter()
+            .map(|id| Part {
+                id: id.to_string(),
+                body: format!("Part {id}"),
+            })
+            .collect()
+    }
+
+    fn status(deliveries: &Deliveries, id: &str) -> DeliveryStatus {
+        deliveries
+            .list()
+            .into_iter()
+            .find(|delivery| delivery.id == id)
+            .unwrap()
+            .status
+    }
+
+    #[test]
+    fn test_message_is_delivered_when_all_parts_are_confirmed() {
+        let deliveries = Deliveries::new();
+        deliveries.sent(&message("msg1"), parts(&["msg1", "part2"]));
+
+        deliveries.confirmed("msg1");
+        assert_eq!(status(&deliveries, "msg1"), DeliveryStatus::Pending);
+
+        deliveries.confirmed("part2");
+        assert_eq!(status(&deliveries, "msg1"), DeliveryStatus::Delivered);
+    }
+
+    #[test]
+    fn test_unconfirmed_messages_are_retried_until_they_fail() {
+        let deliveries = Deliveries::with_timeout(Duration::ZERO, 2);
+        deliveries.sent(&message("msg1"), parts(&["msg1"]));
+
+        let retries = deliveries.take_unconfirmed();
+        assert_eq!(
+            retries,
+            vec![Retry {
+                message: message("msg1"),
+                parts: parts(&["msg1"]),
+            }]
+        );
+
+        assert!(deliveries.take_unconfirmed().is_empty());
+        assert_eq!(
+            status(&deliveries, "msg1"),
+            DeliveryStatus::Failed {
+                reason: "Not confirmed".to_string()
+            }
+        );
+        assert_eq!(deliveries.list()[0].attempts, 2);
+    }
+
+    #[test]
+    fn test_only_unconfirmed_parts_are_retried() {
+        let deliveries = Deliveries::with_timeout(Duration::ZERO, 3);
+        deliveries.sent(&message("msg1"), parts(&["msg1", "part2", "part3"]));
+        deliveries.confirmed("part2");
+
+        assert_eq!(
+            deliveries.take_unconfirmed(),
+            vec![Retry {
+                message: message("msg1"),
+                parts: parts(&["msg1", "part3"]),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_direct_messages_are_not_retried_without_receipt() {
+        let deliveries = Deliveries::with_timeout(Duration::ZERO, 3);
+        let message = OutgoingMessage::with_id(
+            "msg1",
+            RoomId::User("admin@example.org".parse().unwrap()),
+            "Hello",
+        );
+        deliveries.sent(&message, parts(&["msg1"]));
+
+        assert!(deliveries.take_unconfirmed().is_empty());
+        assert_eq!(status(&deliveries, "msg1"), DeliveryStatus::Unconfirmed);
+
+        // The recipient came online and their client sent the receipt.
+        deliveries.confirmed("msg1");
+        assert_eq!(status(&deliveries, "msg1"), DeliveryStatus::Delivered);
+    }
+
+    #[test]
+    fn test_rejected_messages_are_retried_immediately() {
+        let deliveries = Deliveries::new();
+        deliveries.sent(&message("msg1"), parts(&["msg1"]));
+        deliveries.sent(&message("msg2"), parts(&["msg2"]));
+
+        deliveries.rejected("msg1", "not-acceptable".to_string());
+
+        assert_eq!(
+            deliveries.take_unconfirmed(),
+            vec![Retry {
+                message: message("msg1"),
+                parts: parts(&["msg1"]),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_messages_that_were_not_sent_fail() {
+        let deliveries = Deliveries::with_timeout(Duration::ZERO, 3);
+        deliveries.failed(&message("msg1"), "Room was left".to_string());
+
+        assert!(deliveries.take_unconfirmed().is_empty());
+        assert_eq!(
+            status(&deliveries, "msg1"),
+            DeliveryStatus::Failed {
+                reason: "Room was left".to_string()
+            }
+        );
+        assert_eq!(deliveries.list()[0].attempts, 0);
+    }
+
+    #[test]
+    fn test_confirmed_messages_are_not_retried() {
+        let deliveries = Deliveries::with_timeout(Duration::ZERO, 3);
+        deliveries.sent(&message("msg1"), parts(&["msg1"]));
+        deliveries.confirmed("msg1");
+
+        assert!(deliveries.take_unconfirmed().is_empty());
+    }
+}
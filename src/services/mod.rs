@@ -1,4 +1,7 @@
 pub use xmpp_service::XMPPService;
 
+pub mod deliveries;
+pub mod rooms;
+pub mod split_message;
 pub mod xmpp_handle;
 pub mod xmpp_service;
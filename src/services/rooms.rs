@@ -0,0 +1,661 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use prose_xmpp::{BareJid, FullJid};
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+
+use crate::config::RoomSettings;
+use crate::services::xmpp_service::{RoomState, RoomStatus};
+
+/// The delay before the first attempt to rejoin a room. It doubles with every failed attempt.
+const INITIAL_REJOIN_DELAY: Duration = Duration::from_secs(1);
+const MAX_REJOIN_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// Enters and leaves rooms, i.e. the XMPP client or a mock in tests.
+pub trait RoomConnector: Clone + Send + Sync + 'static {
+    /// Enters the room of `occupant`, i.e. room@service/nickname, with the presence status
+    /// `status` and resolves once the room confirmed it.
+    fn enter_room(
+        &self,
+        occupant: &FullJid,
+        password: Option<&str>,
+        status: Option<&str>,
+    ) -> impl Future<Output = Result<()>> + Send;
+    fn exit_room(&self, occupant: &FullJid) -> Result<()>;
+}
+
+/// Keeps the bot in the rooms it's supposed to be in. Rooms are rejoined with exponential backoff
+/// when entering them fails or the bot is removed from them, e.g. because it was kicked, the room
+/// was destroyed or the MUC service restarted. Rooms that refuse the bot for good, e.g. because it
+/// was banned, aren't rejoined.
+#[derive(Debug, Clone)]
+pub struct Rooms {
+    settings: Arc<HashMap<BareJid, RoomSettings>>,
+    /// The nickname of the bot in rooms without a configured nickname.
+    default_nickname: String,
+    /// The status text of the bot's presence in rooms.
+    status: Option<String>,
+    /// The rooms the bot is supposed to be in and whether it is.
+    states: Arc<Mutex<HashMap<BareJid, RoomState>>>,
+    /// The rooms last passed to `set_rooms`.
+    listed: Arc<Mutex<Vec<BareJid>>>,
+    /// Notified with the room whenever an attempt to enter it finished or it was removed.
+    state_listener: Option<mpsc::UnboundedSender<BareJid>>,
+    /// The rooms the bot was invited to, with their passwords. They are kept when the rooms are
+    /// set.
+    invitations: Arc<Mutex<HashMap<BareJid, Option<String>>>>,
+    initial_delay: Duration,
+    max_delay: Duration,
+}
+
+impl Rooms {
+    pub fn new(settings: Vec<RoomSettings>) -> Self {
+        Self {
+            settings: Arc::new(
+                settings
+                    .into_iter()
+                    .map(|settings| (settings.room.clone(), settings))
+                    .collect(),
+            ),
+            default_nickname: "bot".to_string(),
+            status: None,
+            states: Default::default(),
+            listed: Default::default(),
+            state_listener: None,
+            invitations: Default::default(),
+            initial_delay: INITIAL_REJOIN_DELAY,
+            max_delay: MAX_REJOIN_DELAY,
+        }
+    }
+
+    pub fn with_default_nickname(mut self, nickname: impl Into<String>) -> Self {
+        self.default_nickname = nickname.into();
+        self
+    }
+
+    pub fn with_status(mut self, status: Option<String>) -> Self {
+        self.status = status;
+        self
+    }
+
+    /// Sends the room to `listener` whenever an attempt to enter it finished or it was removed.
+    pub fn with_state_listener(mut self, listener: mpsc::UnboundedSender<BareJid>) -> Self {
+        self.state_listener = Some(listener);
+        self
+    }
+
+    pub fn with_backoff(mut self, initial_delay: Duration, max_delay: Duration) -> Self {
+        self.initial_delay = initial_delay;
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// The nickname of the bot in `room`.
+    pub fn nickname(&self, room: &BareJid) -> &str {
+        self.settings
+            .get(room)
+            .and_then(|settings| settings.nickname.as_deref())
+            .unwrap_or(&self.default_nickname)
+    }
+
+    /// The JID of the bot in `room`, i.e. room@service/nickname.
+    pub fn occupant_jid(&self, room: &BareJid) -> Result<FullJid> {
+        Ok(room.with_resource_str(self.nickname(room))?)
+    }
+
+    fn password(&self, room: &BareJid) -> Option<String> {
+        self.settings
+            .get(room)
+            .and_then(|settings| settings.password.clone())
+            .or_else(|| {
+                self.invitations
+                    .lock()
+                    .unwrap()
+                    .get(room)
+                    .cloned()
+                    .flatten()
+            })
+    }
+
+    /// Returns `true` if `room` is hosted by the MUC service of a configured or listed room.
+    pub fn is_known_service(&self, room: &BareJid) -> bool {
+        let listed = self.listed.lock().unwrap();
+        listed
+            .iter()
+            .chain(self.settings.keys())
+            .any(|known| known.domain() == room.domain())
+    }
+
+    /// Returns `true` if the bot entered `room` because it was invited to it.
+    pub fn is_invited(&self, room: &BareJid) -> bool {
+        self.invitations.lock().unwrap().contains_key(room)
+    }
+
+    /// Returns the membership state of every room the bot is supposed to be in.
+    pub fn states(&self) -> Vec<RoomStatus> {
+        let mut states = self
+            .states
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(room, state)| RoomStatus {
+                room: room.clone(),
+                state: state.clone(),
+            })
+            .collect::<Vec<_>>();
+        states.sort_by_key(|status| status.room.to_string());
+        states
+    }
+
+    /// Returns the membership state of `room`, or `None` if the bot isn't supposed to be in it.
+    pub fn state(&self, room: &BareJid) -> Option<RoomState> {
+        self.states.lock().unwrap().get(room).cloned()
+    }
+
+    /// Enters the rooms in `rooms` the bot isn't supposed to be in yet and leaves the ones no
+    /// longer listed, unless the bot was invited to them.
+    pub fn set_rooms<C: RoomConnector>(&self, connector: &C, rooms: Vec<BareJid>) {
+        let mut states = self.states.lock().unwrap();
+        let invitations = self.invitations.lock().unwrap();
+        *self.listed.lock().unwrap() = rooms.clone();
+
+        let removed = states
+            .keys()
+            .filter(|room| !rooms.contains(room) && !invitations.contains_key(room))
+            .cloned()
+            .collect::<Vec<_>>();
+        for room in removed {
+            states.remove(&room);
+            self.notify_state_changed(&room);
+            info!("Leaving room {room}…");
+            if let Err(err) = self
+                .occupant_jid(&room)
+                .and_then(|occupant| connector.exit_room(&occupant))
+            {
+                warn!("Failed to leave room {room}: {err}");
+            }
+        }
+
+        for room in rooms {
+            if states.contains_key(&room) {
+                continue;
+            }
+            states.insert(room.clone(), RoomState::Joining);
+            self.spawn_join(connector.clone(), room);
+        }
+    }
+
+    /// Enters `room` the bot was invited to and stays in it.
+    pub fn accept_invitation<C: RoomConnector>(
+        &self,
+        connector: &C,
+        room: BareJid,
+        password: Option<String>,
+    ) {
+        self.invitations
+            .lock()
+            .unwrap()
+            .insert(room.clone(), password);
+
+        // Being invited again is a chance that the bot was unbanned or made a member.
+        let mut states = self.states.lock().unwrap();
+        if states
+            .get(&room)
+            .is_some_and(|state| !matches!(state, RoomState::Failed { .. }))
+        {
+            return;
+        }
+        states.insert(room.clone(), RoomState::Joining);
+        self.spawn_join(connector.clone(), room);
+    }
+
+    /// Forgets the invitation to `room` and leaves it, unless events are posted in it. Returns
+    /// `false` if the bot wasn't invited to `room`.
+    pub fn leave_invited<C: RoomConnector>(&self, connector: &C, room: &BareJid) -> bool {
+        let mut states = self.states.lock().unwrap();
+        if self.invitations.lock().unwrap().remove(room).is_none() {
+            return false;
+        }
+        if self.listed.lock().unwrap().contains(room) {
+            return true;
+        }
+
+        states.remove(room);
+        self.notify_state_changed(room);
+        info!("Leaving room {room}…");
+        if let Err(err) = self
+            .occupant_jid(room)
+            .and_then(|occupant| connector.exit_room(&occupant))
+        {
+            warn!("Failed to leave room {room}: {err}");
+        }
+        true
+    }
+
+    /// Enters all rooms again, e.g. after reconnecting. Rooms that are waiting to be rejoined
+    /// already or refused the bot are left alone.
+    pub fn enter_all<C: RoomConnector>(&self, connector: &C) {
+        let mut states = self.states.lock().unwrap();
+
+        for (room, state) in states.iter_mut() {
+            if matches!(
+                state,
+                RoomState::Rejoining { .. } | RoomState::Joining | RoomState::Failed { .. }
+            ) {
+                continue;
+            }
+            *state = RoomState::Joining;
+            self.spawn_join(connector.clone(), room.clone());
+        }
+    }
+
+    /// Handles being removed from `room`, or the room reporting an error. Rejoins the room if the
+    /// bot is supposed to be in it.
+    pub fn room_left<C: RoomConnector>(&self, connector: &C, room: &BareJid, reason: String) {
+        let mut states = self.states.lock().unwrap();
+
+        // Rooms that are being entered already are retried by their join task.
+        let Some(state) = states
+            .get_mut(room)
+            .filter(|state| **state == RoomState::Joined)
+        else {
+            return;
+        };
+
+        warn!("Left room {room}: {reason}. Rejoining…");
+        *state = RoomState::Rejoining { reason, attempt: 0 };
+        self.spawn_join(connector.clone(), room.clone());
+    }
+
+    /// Handles `room` refusing the bot for good, e.g. because it was banned. The room isn't
+    /// rejoined, also not if it is being entered right now.
+    pub fn room_failed(&self, room: &BareJid, reason: String) {
+        let mut states = self.states.lock().unwrap();
+        let Some(state) = states.get_mut(room) else {
+            return;
+        };
+
+        error!("Can't enter room {room}: {reason}. Not rejoining it.");
+        *state = RoomState::Failed { reason };
+        self.notify_state_changed(room);
+    }
+
+    fn spawn_join<C: RoomConnector>(&self, connector: C, room: BareJid) {
+        let rooms = self.clone();
+        tokio::spawn(async move { rooms.join(connector, room).await });
+    }
+
+    async fn join<C: RoomConnector>(&self, connector: C, room: BareJid) {
+        let mut attempt = match self.state(&room) {
+            Some(RoomState::Rejoining { attempt, .. }) => {
+                tokio::time::sleep(self.delay(attempt)).await;
+                if !matches!(self.state(&room), Some(RoomState::Rejoining { .. })) {
+                    return;
+                }
+                attempt + 1
+            }
+            Some(RoomState::Failed { .. }) | None => return,
+            Some(_) => 0,
+        };
+
+        loop {
+            info!("Entering room {room}…");
+            let password = self.password(&room);
+            let result = match self.occupant_jid(&room) {
+                Ok(occupant) => {
+                    connector
+                        .enter_room(&occupant, password.as_deref(), self.status.as_deref())
+                        .await
+                }
+                Err(err) => Err(err),
+            };
+
+            {
+                let mut states = self.states.lock().unwrap();
+                // The room was removed in the meantime.
+                let Some(state) = states.get_mut(&room) else {
+                    return;
+                };
+
+                let is_joined = result.is_ok();
+                match result {
+                    Ok(()) => *state = RoomState::Joined,
+                    // The room reported why entering it failed already.
+                    Err(_) if matches!(state, RoomState::Failed { .. }) => return,
+                    Err(err) => {
+                        warn!("Failed to enter room {room}: {err}");
+                        *state = RoomState::Rejoining {
+                            reason: err.to_string(),
+                            attempt,
+                        };
+                    }
+                }
+                self.notify_state_changed(&room);
+                if is_joined {
+                    return;
+                }
+            }
+
+            tokio::time::sleep(self.delay(attempt)).await;
+            if !matches!(self.state(&room), Some(RoomState::Rejoining { .. })) {
+                return;
+            }
+            attempt += 1;
+        }
+    }
+
+    fn notify_state_changed(&self, room: &BareJid) {
+        if let Some(listener) = &self.state_listener {
+            _ = listener.send(room.clone());
+        }
+    }
+
+    fn delay(&self, attempt: u32) -> Duration {
+        self.initial_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use anyhow::bail;
+
+    use super::*;
+
+    /// Fails to enter rooms `failures` times, then succeeds. Entering a room takes `delay`.
+    #[derive(Clone, Default)]
+    struct MockConnector {
+        failures: Arc<AtomicU32>,
+        delay: Duration,
+        entered: Arc<Mutex<Vec<(FullJid, Option<String>)>>>,
+        exited: Arc<Mutex<Vec<FullJid>>>,
+    }
+
+    impl RoomConnector for MockConnector {
+        async fn enter_room(
+            &self,
+            occupant: &FullJid,
+            password: Option<&str>,
+            _status: Option<&str>,
+        ) -> Result<()> {
+            tokio::time::sleep(self.delay).await;
+            if self.failures.load(Ordering::SeqCst) > 0 {
+                self.failures.fetch_sub(1, Ordering::SeqCst);
+                bail!("service-unavailable");
+            }
+            self.entered
+                .lock()
+                .unwrap()
+                .push((occupant.clone(), password.map(ToString::to_string)));
+            Ok(())
+        }
+
+        fn exit_room(&self, occupant: &FullJid) -> Result<()> {
+            self.exited.lock().unwrap().push(occupant.clone());
+            Ok(())
+        }
+    }
+
+    fn room() -> BareJid {
+        "room@example.org".parse().unwrap()
+    }
+
+    fn occupant() -> FullJid {
+        "room@example.org/bot".parse().unwrap()
+    }
+
+    fn rooms() -> Rooms {
+        Rooms::new(vec![]).with_backoff(Duration::from_millis(10), Duration::from_millis(40))
+    }
+
+    async fn settle() {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_enters_rooms() {
+        let connector = MockConnector::default();
+        let rooms = rooms();
+
+        rooms.set_rooms(&connector, vec![room()]);
+        settle().await;
+
+        assert_eq!(*connector.entered.lock().unwrap(), vec![(occupant(), None)]);
+        assert_eq!(
+            rooms.states(),
+            vec![RoomStatus {
+                room: room(),
+                state: RoomState::Joined
+            }]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retries_entering_with_backoff() {
+        let connector = MockConnector::default();
+        connector.failures.store(3, Ordering::SeqCst);
+        let rooms = rooms();
+
+        rooms.set_rooms(&connector, vec![room()]);
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert_eq!(
+            rooms.states()[0].state,
+            RoomState::Rejoining {
+                reason: "service-unavailable".to_string(),
+                attempt: 0
+            }
+        );
+
+        settle().await;
+        assert_eq!(rooms.states()[0].state, RoomState::Joined);
+        assert_eq!(*connector.entered.lock().unwrap(), vec![(occupant(), None)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rejoins_after_being_removed() {
+        let connector = MockConnector::default();
+        let rooms = rooms();
+        rooms.set_rooms(&connector, vec![room()]);
+        settle().await;
+
+        rooms.room_left(&connector, &room(), "kicked".to_string());
+        assert_eq!(
+            rooms.states()[0].state,
+            RoomState::Rejoining {
+                reason: "kicked".to_string(),
+                attempt: 0
+            }
+        );
+
+        settle().await;
+        assert_eq!(rooms.states()[0].state, RoomState::Joined);
+        assert_eq!(connector.entered.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rooms_refusing_the_bot_are_not_rejoined() {
+        let connector = MockConnector {
+            delay: Duration::from_millis(5),
+            ..Default::default()
+        };
+        connector.failures.store(1, Ordering::SeqCst);
+        let rooms = rooms();
+
+        // The room reports the error before entering it fails.
+        rooms.set_rooms(&connector, vec![room()]);
+        rooms.room_failed(&room(), "The room is members-only".to_string());
+        settle().await;
+        rooms.enter_all(&connector);
+        rooms.set_rooms(&connector, vec![room()]);
+        settle().await;
+
+        assert!(connector.entered.lock().unwrap().is_empty());
+        assert_eq!(
+            rooms.states()[0].state,
+            RoomState::Failed {
+                reason: "The room is members-only".to_string()
+            }
+        );
+
+        // Being invited again is worth another attempt.
+        rooms.accept_invitation(&connector, room(), None);
+        settle().await;
+        assert_eq!(rooms.states()[0].state, RoomState::Joined);
+
+        rooms.room_failed(&room(), "Banned from the room".to_string());
+        settle().await;
+        assert_eq!(connector.entered.lock().unwrap().len(), 1);
+        assert_eq!(
+            rooms.states()[0].state,
+            RoomState::Failed {
+                reason: "Banned from the room".to_string()
+            }
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_removed_rooms_are_left_and_not_rejoined() {
+        let connector = MockConnector::default();
+        connector.failures.store(1, Ordering::SeqCst);
+        let rooms = rooms();
+        rooms.set_rooms(&connector, vec![room()]);
+        tokio::time::sleep(Duration::from_millis(5)).await;
+
+        rooms.set_rooms(&connector, vec![]);
+        rooms.room_left(&connector, &room(), "kicked".to_string());
+        settle().await;
+
+        assert!(rooms.states().is_empty());
+        assert!(connector.entered.lock().unwrap().is_empty());
+        assert_eq!(*connector.exited.lock().unwrap(), vec![occupant()]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_enters_rooms_with_configured_password_and_nickname() {
+        let connector = MockConnector::default();
+        let rooms = Rooms::new(vec![RoomSettings {
+            room: room(),
+            password: Some("secret".to_string()),
+            nickname: Some("GitHub".to_string()),
+        }]);
+
+        rooms.set_rooms(&connector, vec![room()]);
+        settle().await;
+
+        assert_eq!(rooms.nickname(&room()), "GitHub");
+        assert_eq!(
+            *connector.entered.lock().unwrap(),
+            vec![(
+                "room@example.org/GitHub".parse().unwrap(),
+                Some("secret".to_string())
+            )]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_default_nickname_is_configurable() {
+        let connector = MockConnector::default();
+        let rooms = rooms().with_default_nickname("GitHub");
+
+        rooms.set_rooms(&connector, vec![room()]);
+        settle().await;
+
+        assert_eq!(
+            *connector.entered.lock().unwrap(),
+            vec![("room@example.org/GitHub".parse().unwrap(), None)]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rooms_invited_to_are_kept() {
+        let connector = MockConnector::default();
+        let rooms = rooms();
+
+        rooms.accept_invitation(&connector, room(), Some("secret".to_string()));
+        rooms.set_rooms(&connector, vec![]);
+        settle().await;
+
+        assert_eq!(rooms.states()[0].state, RoomState::Joined);
+        assert_eq!(
+            *connector.entered.lock().unwrap(),
+            vec![(occupant(), Some("secret".to_string()))]
+        );
+        assert!(connector.exited.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rooms_invited_to_can_be_left() {
+        let connector = MockConnector::default();
+        let rooms = rooms();
+        let listed = "listed@example.org".parse::<BareJid>().unwrap();
+
+        rooms.set_rooms(&connector, vec![listed.clone()]);
+        rooms.accept_invitation(&connector, room(), None);
+        rooms.accept_invitation(&connector, listed.clone(), None);
+        settle().await;
+
+        assert!(!rooms.leave_invited(&connector, &"other@example.org".parse().unwrap()));
+        assert!(rooms.leave_invited(&connector, &room()));
+        assert!(rooms.leave_invited(&connector, &listed));
+
+        assert_eq!(*connector.exited.lock().unwrap(), vec![occupant()]);
+        assert_eq!(rooms.states().len(), 1);
+        assert!(!rooms.is_invited(&listed));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_known_services_are_those_of_configured_and_listed_rooms() {
+        let connector = MockConnector::default();
+        let rooms = Rooms::new(vec![RoomSettings {
+            room: "room@muc.example.org".parse().unwrap(),
+            password: None,
+            nickname: None,
+        }]);
+        rooms.set_rooms(
+            &connector,
+            vec!["room@conference.example.com".parse().unwrap()],
+        );
+
+        assert!(rooms.is_known_service(&"other@muc.example.org".parse().unwrap()));
+        assert!(rooms.is_known_service(&"other@conference.example.com".parse().unwrap()));
+        assert!(!rooms.is_known_service(&"room@evil.example.net".parse().unwrap()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_notifies_state_changes() {
+        let connector = MockConnector::default();
+        connector.failures.store(1, Ordering::SeqCst);
+        let (listener, mut changes) = mpsc::unbounded_channel();
+        let rooms = rooms().with_state_listener(listener);
+
+        rooms.set_rooms(&connector, vec![room()]);
+        assert_eq!(rooms.state(&room()), Some(RoomState::Joining));
+
+        assert_eq!(changes.recv().await, Some(room()));
+        assert!(matches!(
+            rooms.state(&room()),
+            Some(RoomState::Rejoining { .. })
+        ));
+        assert_eq!(changes.recv().await, Some(room()));
+        assert_eq!(rooms.state(&room()), Some(RoomState::Joined));
+
+        rooms.set_rooms(&connector, vec![]);
+        assert_eq!(changes.recv().await, Some(room()));
+        assert_eq!(rooms.state(&room()), None);
+    }
+
+    #[test]
+    fn test_delay_is_capped() {
+        let rooms = Rooms::new(vec![]);
+        assert_eq!(rooms.delay(0), Duration::from_secs(1));
+        assert_eq!(rooms.delay(3), Duration::from_secs(8));
+        assert_eq!(rooms.delay(40), MAX_REJOIN_DELAY);
+    }
+}
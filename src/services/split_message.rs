@@ -0,0 +1,202 @@
+use crate::templates::filters::{code_fence, toggle_fence};
+
+/// Splits a markdown `message` into messages of at most `max_size` bytes. Messages are split
+/// between blocks (paragraphs, lists, code blocks, …) where possible, between lines if a single
+/// block is too large and anywhere as a last resort. A `max_size` of `0` disables splitting.
+pub fn split_message(message: &str, max_size: usize) -> Vec<String> {
+    if max_size == 0 || message.len() <= max_size {
+        return vec![message.to_string()];
+    }
+
+    let mut parts = vec![];
+    let mut current = String::new();
+
+    for piece in blocks(message)
+        .iter()
+        .flat_map(|block| split_block(block, max_size))
+    {
+        if !current.is_empty() && current.len() + 2 + piece.len() > max_size {
+            parts.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push_str("\n\n");
+        }
+        current.push_str(&piece);
+    }
+
+    if !current.is_empty() {
+        parts.push(current);
+    }
+
+    parts
+}
+
+/// Splits `message` at blank lines outside of fenced code blocks.
+fn blocks(message: &str) -> Vec<String> {
+    let mut blocks = vec![];
+    let mut current = vec![];
+    let mut open_fence: Option<&str> = None;
+
+    for line in message.lines() {
+        if let Some(fence) = code_fence(line) {
+            open_fence = toggle_fence(open_fence, fence);
+        }
+
+        if line.trim().is_empty() && open_fence.is_none() {
+            if !current.is_empty() {
+                blocks.push(current.join("\n"));
+                current.clear();
+            }
+            continue;
+        }
+
+        current.push(line);
+    }
+
+    if !current.is_empty() {
+        blocks.push(current.join("\n"));
+    }
+
+    blocks
+}
+
+/// Splits a single block into pieces of at most `max_size` bytes, preferably between lines. Code
+/// blocks that are split are closed at the end of a piece and opened again in the next one.
+fn split_block(block: &str, max_size: usize) -> Vec<String> {
+    if block.len() <= max_size {
+        return vec![block.to_string()];
+    }
+
+    let mut pieces = vec![];
+    let mut current = String::new();
+    // The line that opened the current code block, and its fence.
+    let mut open_block: Option<(&str, &str)> = None;
+
+    for line in block.lines() {
+        let next_block = match code_fence(line) {
+            Some(fence) => match toggle_fence(open_block.map(|(_, fence)| fence), fence) {
+                None => None,
+                Some(_) if open_block.is_some() => open_block,
+                Some(fence) => Some((line, fence)),
+            },
+            None => open_block,
+        };
+        // Leave room for closing the code block that is open after this line.
+        let closing_size = next_block.map_or(0, |(_, fence)| fence.len() + 1);
+        let reopening_size = open_block.map_or(0, |(opening, _)| opening.len() + 1);
+        let limit = max_size
+            .saturating_sub(reopening_size + closing_size)
+            .max(1);
+
+        for piece in split_line(line, limit) {
+            if !current.is_empty() && current.len() + 1 + piece.len() + closing_size > max_size {
+                if let Some((opening, fence)) = open_block {
+                    current.push('\n');
+                    current.push_str(fence);
+                    pieces.push(std::mem::take(&mut current));
+                    current.push_str(opening);
+                } else {
+                    pieces.push(std::mem::take(&mut current));
+                }
+            }
+            if !current.is_empty() {
+                current.push('\n');
+            }
+            current.push_str(piece);
+        }
+
+        open_block = next_block;
+    }
+
+    if !current.is_empty() {
+        pieces.push(current);
+    }
+
+    pieces
+}
+
+/// Splits `line` into pieces of at most `max_size` bytes without splitting characters.
+fn split_line(line: &str, max_size: usize) -> Vec<&str> {
+    let mut pieces = vec![];
+    let mut rest = line;
+
+    while rest.len() > max_size {
+        let mut idx = max_size;
+        while !rest.is_char_boundary(idx) {
+            idx -= 1;
+        }
+        // Never return an empty piece, even if a single character exceeds `max_size`.
+        if idx == 0 {
+            idx = rest
+                .chars()
+                .next()
+                .map(char::len_utf8)
+                .unwrap_or(rest.len());
+        }
+        pieces.push(&rest[..idx]);
+        rest = &rest[idx..];
+    }
+
+    pieces.push(rest);
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_short_message_is_not_split() {
+        assert_eq!(split_message("Hello", 10), vec!["Hello"]);
+        assert_eq!(split_message("Hello", 0), vec!["Hello"]);
+    }
+
+    #[test]
+    fn test_splits_between_blocks() {
+        assert_eq!(
+            split_message("First\n\nSecond\n\nThird", 15),
+            vec!["First\n\nSecond", "Third"]
+        );
+    }
+
+    #[test]
+    fn test_keeps_code_blocks_with_blank_lines_together() {
+        assert_eq!(
+            split_message("Intro\n\n```\na\n\nb\n```", 12),
+            vec!["Intro", "```\na\n\nb\n```"]
+        );
+    }
+
+    #[test]
+    fn test_only_matching_fences_close_code_blocks() {
+        assert_eq!(
+            split_message("Intro\n\n````\n```\n\nb\n````", 20),
+            vec!["Intro", "````\n```\n\nb\n````"]
+        );
+        assert_eq!(
+            split_message("Intro\n\n~~~\n```\n\nb\n~~~", 18),
+            vec!["Intro", "~~~\n```\n\nb\n~~~"]
+        );
+    }
+
+    #[test]
+    fn test_split_code_blocks_are_fenced_again() {
+        assert_eq!(
+            split_message("```rust\nlet a = 1;\nlet b = 2;\n```", 25),
+            vec!["```rust\nlet a = 1;\n```", "```rust\nlet b = 2;\n```"]
+        );
+    }
+
+    #[test]
+    fn test_splits_large_blocks_between_lines() {
+        assert_eq!(
+            split_message("- one\n- two\n- three", 12),
+            vec!["- one\n- two", "- three"]
+        );
+    }
+
+    #[test]
+    fn test_splits_long_lines_at_char_boundaries() {
+        assert_eq!(split_message("ääää", 3), vec!["ä", "ä", "ä", "ä"]);
+    }
+}
@@ -1,24 +1,55 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 
-use anyhow::Result;
-use futures::FutureExt;
+use anyhow::{format_err, Result};
+use base64::prelude::{Engine, BASE64_STANDARD};
 use prose_markup::MarkdownParser;
 use prose_xmpp::connector::xmpp_rs::Connector;
-use prose_xmpp::stanza::message::MessageType;
-use prose_xmpp::stanza::presence::Show;
+use prose_xmpp::minidom::Element;
+use prose_xmpp::stanza::avatar::ImageId;
+use prose_xmpp::stanza::message::{MessageType, Reply};
+use prose_xmpp::stanza::muc::user::Status as MucStatus;
+use prose_xmpp::stanza::muc::{
+    Affiliation as MucAffiliation, DirectInvite, MediatedInvite, MucUser,
+};
+use prose_xmpp::stanza::presence::{Presence, Show, Type as PresenceType};
 use prose_xmpp::stanza::Message;
 use prose_xmpp::{
-    client::Event as ClientEvent, mods, mods::chat::Event as ChatEvent, BareJid, Client,
-    ConnectionError, Event, IDProvider, Jid, Secret, UUIDProvider,
+    client::Event as ClientEvent, mods, mods::chat::Event as ChatEvent,
+    mods::muc::Event as MucEvent, mods::status::Event as StatusEvent, BareJid, Client,
+    ConnectionError, Event, FullJid, Jid, Secret,
 };
-use tokio::sync::mpsc;
-use tracing::{info, warn};
+use sha1::{Digest, Sha1};
+use tokio::sync::{mpsc, watch};
+use tracing::{error, info, warn};
 
 use crate::config::XMPPSettings;
-use crate::services::xmpp_service::RoomId;
+use crate::services::deliveries::{Deliveries, Part, Retry};
+use crate::services::rooms::{RoomConnector, Rooms};
+use crate::services::split_message::split_message;
+use crate::services::xmpp_service::{
+    new_message_id, Affiliation, Delivery, IncomingMessage, MessageHandler, OutgoingMessage,
+    RoomId, RoomState, RoomStatus,
+};
 use crate::services::XMPPService as XMPPServiceTrait;
 
+const STANZA_ERROR_NS: &str = "urn:ietf:params:xml:ns:xmpp-stanzas";
+const RECEIPTS_NS: &str = "urn:xmpp:receipts";
+const SID_NS: &str = "urn:xmpp:sid:0";
+/// The errors with which rooms refuse the bot until their settings or ours change, so that
+/// entering them again is pointless.
+const TERMINAL_ROOM_ERRORS: [&str; 3] = ["forbidden", "registration-required", "not-authorized"];
+/// How often messages that weren't confirmed in time are looked for.
+const RETRY_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// How many messages to a room wait for the bot to enter it, e.g. after reconnecting. The oldest
+/// ones are dropped beyond that.
+const MAX_QUEUED_MESSAGES: usize = 100;
+/// How often the connection is checked and reestablished if it was lost.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
 impl From<RoomId> for Jid {
     fn from(value: RoomId) -> Self {
         match value {
@@ -31,34 +62,122 @@ impl From<RoomId> for Jid {
 
 #[derive(Clone)]
 pub struct XMPPHandle {
-    sender: mpsc::Sender<XMPPServiceMessage>,
+    sender: mpsc::UnboundedSender<XMPPServiceMessage>,
+    /// The rooms to be in. Only the latest list matters, so it's passed via a watch channel
+    /// rather than the message queue, where it could be dropped.
+    listed_rooms: Arc<watch::Sender<Vec<BareJid>>>,
+    message_handler: Arc<RwLock<Option<Arc<dyn MessageHandler>>>>,
+    rooms: Rooms,
+    deliveries: Deliveries,
 }
 
 impl XMPPHandle {
-    pub fn new(config: XMPPSettings, rooms: Vec<BareJid>) -> Self {
-        let (sender, receiver) = mpsc::channel(20);
-        let mut actor = XMPPService::new(config, rooms, receiver);
+    pub fn new(config: XMPPSettings) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let (listed_rooms, listed_rooms_receiver) = watch::channel(vec![]);
+        let (room_state_listener, room_state_changes) = mpsc::unbounded_channel();
+        let message_handler = Arc::new(RwLock::new(None));
+        let rooms = Rooms::new(config.rooms.clone())
+            .with_default_nickname(config.nickname.clone())
+            .with_status(config.status.clone())
+            .with_state_listener(room_state_listener);
+        let deliveries = Deliveries::new();
+        let mut actor = XMPPService::new(
+            config,
+            receiver,
+            listed_rooms_receiver,
+            room_state_changes,
+            message_handler.clone(),
+            rooms.clone(),
+            deliveries.clone(),
+        );
         tokio::spawn(async move { actor.run().await });
-        Self { sender }
+        tokio::spawn(retry_unconfirmed(deliveries.clone(), sender.clone()));
+        Self {
+            sender,
+            listed_rooms: Arc::new(listed_rooms),
+            message_handler,
+            rooms,
+            deliveries,
+        }
     }
 }
 
 impl XMPPServiceTrait for XMPPHandle {
-    fn send_message(&self, to: RoomId, message: String) {
-        self.sender
-            .send(XMPPServiceMessage::SendMessage { to, body: message })
-            .now_or_never();
+    fn send_message(&self, message: OutgoingMessage) {
+        let sent = self
+            .sender
+            .send(XMPPServiceMessage::SendMessage(message.clone()));
+        if sent.is_err() {
+            error!(
+                "Failed to send message {}: XMPP service stopped.",
+                message.id
+            );
+            self.deliveries
+                .failed(&message, "XMPP service stopped".to_string());
+        }
+    }
+
+    fn set_message_handler(&self, handler: Arc<dyn MessageHandler>) {
+        *self.message_handler.write().unwrap() = Some(handler);
+    }
+
+    fn set_rooms(&self, rooms: Vec<BareJid>) {
+        self.listed_rooms.send_replace(rooms);
+    }
+
+    fn leave_room(&self, room: BareJid) -> bool {
+        if !self.rooms.is_invited(&room) {
+            return false;
+        }
+        _ = self.sender.send(XMPPServiceMessage::LeaveRoom(room));
+        true
+    }
+
+    fn room_states(&self) -> Vec<RoomStatus> {
+        self.rooms.states()
+    }
+
+    fn deliveries(&self) -> Vec<Delivery> {
+        self.deliveries.list()
+    }
+}
+
+/// Periodically sends the messages again that weren't confirmed in time.
+async fn retry_unconfirmed(
+    deliveries: Deliveries,
+    sender: mpsc::UnboundedSender<XMPPServiceMessage>,
+) {
+    let mut interval = tokio::time::interval(RETRY_CHECK_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        for retry in deliveries.take_unconfirmed() {
+            info!("Sending unconfirmed message {} again.", retry.message.id);
+            if sender.send(XMPPServiceMessage::ResendParts(retry)).is_err() {
+                return;
+            }
+        }
     }
 }
 
 enum XMPPServiceMessage {
-    SendMessage { to: RoomId, body: String },
+    SendMessage(OutgoingMessage),
+    ResendParts(Retry),
+    LeaveRoom(BareJid),
 }
 
 struct XMPPService {
     config: XMPPSettings,
-    rooms: Vec<BareJid>,
-    receiver: mpsc::Receiver<XMPPServiceMessage>,
+    rooms: Rooms,
+    deliveries: Deliveries,
+    receiver: mpsc::UnboundedReceiver<XMPPServiceMessage>,
+    listed_rooms: watch::Receiver<Vec<BareJid>>,
+    room_state_changes: mpsc::UnboundedReceiver<BareJid>,
+    /// The messages waiting for the bot to enter their room, since rooms reject messages from
+    /// non-occupants.
+    queued: HashMap<BareJid, VecDeque<OutgoingMessage>>,
     client: Client,
     is_connected: Arc<AtomicBool>,
 }
@@ -66,20 +185,33 @@ struct XMPPService {
 impl XMPPService {
     fn new(
         config: XMPPSettings,
-        rooms: Vec<BareJid>,
-        receiver: mpsc::Receiver<XMPPServiceMessage>,
+        receiver: mpsc::UnboundedReceiver<XMPPServiceMessage>,
+        listed_rooms: watch::Receiver<Vec<BareJid>>,
+        room_state_changes: mpsc::UnboundedReceiver<BareJid>,
+        message_handler: Arc<RwLock<Option<Arc<dyn MessageHandler>>>>,
+        rooms: Rooms,
+        deliveries: Deliveries,
     ) -> Self {
         let is_connected = Arc::new(AtomicBool::new(false));
+        let state = EventHandlerState {
+            is_connected: is_connected.clone(),
+            occupants: Default::default(),
+            message_handler,
+            rooms: rooms.clone(),
+            deliveries: deliveries.clone(),
+            accept_invitations_from: if config.accept_invites {
+                config.admins.clone()
+            } else {
+                vec![]
+            },
+        };
 
         let client = Client::builder()
             .set_connector_provider(Connector::provider())
-            .set_event_handler({
-                let is_connected = is_connected.clone();
-                move |client, event| {
-                    let is_connected = is_connected.clone();
-                    async move {
-                        handle_event(client, event, &is_connected);
-                    }
+            .set_event_handler(move |client, event| {
+                let state = state.clone();
+                async move {
+                    handle_event(client, event, &state);
                 }
             })
             .build();
@@ -87,57 +219,238 @@ impl XMPPService {
         Self {
             config,
             rooms,
+            deliveries,
             receiver,
+            listed_rooms,
+            room_state_changes,
+            queued: Default::default(),
             client,
             is_connected,
         }
     }
 
     async fn run(&mut self) {
-        _ = self.connect_if_needed().await;
+        let mut reconnect_interval = tokio::time::interval(RECONNECT_INTERVAL);
 
-        while let Some(msg) = self.receiver.recv().await {
-            _ = self.connect_if_needed().await;
-            _ = self.handle_message(msg).await;
+        loop {
+            tokio::select! {
+                msg = self.receiver.recv() => {
+                    let Some(msg) = msg else {
+                        return;
+                    };
+                    _ = self.connect_if_needed().await;
+                    _ = self.handle_message(msg);
+                }
+                Some(room) = self.room_state_changes.recv() => {
+                    self.handle_room_state_change(&room);
+                }
+                Ok(()) = self.listed_rooms.changed() => {
+                    let rooms = self.listed_rooms.borrow_and_update().clone();
+                    _ = self.connect_if_needed().await;
+                    self.rooms.set_rooms(&self.client, rooms);
+                }
+                _ = reconnect_interval.tick() => {
+                    _ = self.connect_if_needed().await;
+                }
+            }
         }
     }
 
-    async fn handle_message(&mut self, msg: XMPPServiceMessage) -> Result<()> {
+    fn handle_message(&mut self, msg: XMPPServiceMessage) -> Result<()> {
         match msg {
-            XMPPServiceMessage::SendMessage { to, body } => {
-                let chat = self.client.get_mod::<mods::Chat>();
-                let message_type = match &to {
-                    RoomId::User(_) => MessageType::Chat,
-                    RoomId::Room(_) => MessageType::Groupchat,
+            XMPPServiceMessage::SendMessage(outgoing) => {
+                // Rooms reject messages from non-occupants, so messages wait for the bot to enter
+                // the room. They also wait behind messages queued earlier to keep their order.
+                if let RoomId::Room(room) = &outgoing.to {
+                    let state = self.rooms.state(room);
+                    if self.queued.contains_key(room)
+                        || matches!(
+                            state,
+                            Some(RoomState::Joining | RoomState::Rejoining { .. })
+                        )
+                    {
+                        self.queue(room.clone(), outgoing);
+                        return Ok(());
+                    }
+                    if let Some(RoomState::Failed { reason }) = state {
+                        warn!("Dropping message {} to room {room}: {reason}", outgoing.id);
+                        self.deliveries.failed(&outgoing, reason);
+                        return Ok(());
+                    }
+                }
+                self.send_message(outgoing)?;
+            }
+            XMPPServiceMessage::ResendParts(retry) => {
+                self.send_parts(&retry.message, retry.parts)?;
+            }
+            XMPPServiceMessage::LeaveRoom(room) => {
+                self.rooms.leave_invited(&self.client, &room);
+            }
+        }
+        Ok(())
+    }
+
+    /// Keeps `outgoing` until the bot entered `room`.
+    fn queue(&mut self, room: BareJid, outgoing: OutgoingMessage) {
+        let queued = self.queued.entry(room).or_default();
+        if queued.len() >= MAX_QUEUED_MESSAGES {
+            if let Some(dropped) = queued.pop_front() {
+                warn!("Dropping message {}: too many messages queued.", dropped.id);
+                self.deliveries
+                    .failed(&dropped, "Too many messages queued".to_string());
+            }
+        }
+        queued.push_back(outgoing);
+    }
+
+    /// Sends the messages queued for `room` once the bot entered it. They are dropped if the bot
+    /// is no longer supposed to be in `room` or the room refused it.
+    fn handle_room_state_change(&mut self, room: &BareJid) {
+        let reason = match self.rooms.state(room) {
+            Some(RoomState::Joining | RoomState::Rejoining { .. }) => return,
+            Some(RoomState::Joined) => {
+                let Some(queued) = self.queued.remove(room) else {
+                    return;
                 };
+                info!("Sending {} messages queued for room {room}.", queued.len());
+                for outgoing in queued {
+                    if let Err(err) = self.send_message(outgoing) {
+                        warn!("Failed to send message to room {room}: {err}");
+                    }
+                }
+                return;
+            }
+            Some(RoomState::Failed { reason }) => reason,
+            None => "Room was left".to_string(),
+        };
+
+        let Some(queued) = self.queued.remove(room) else {
+            return;
+        };
+        warn!("Dropping {} messages queued for room {room}.", queued.len());
+        for outgoing in queued {
+            self.deliveries.failed(&outgoing, reason.clone());
+        }
+    }
+
+    /// Splits `outgoing` into parts that fit into a stanza each and sends them.
+    fn send_message(&self, outgoing: OutgoingMessage) -> Result<()> {
+        let parts = split_message(&outgoing.body, self.config.max_message_size)
+            .into_iter()
+            .enumerate()
+            .map(|(idx, body)| Part {
+                // The first part carries the id the message is known by, so that it can
+                // be corrected later on.
+                id: if idx == 0 {
+                    outgoing.id.clone()
+                } else {
+                    new_message_id()
+                },
+                body,
+            })
+            .collect::<Vec<_>>();
+        self.deliveries.sent(&outgoing, parts.clone());
+        self.send_parts(&outgoing, parts)
+    }
+
+    /// Sends `parts` of `outgoing`. Parts keep their ids when they're sent again, which are also
+    /// set as origin ids (XEP-0359), so that clients can tell retries are duplicates.
+    fn send_parts(&self, outgoing: &OutgoingMessage, parts: Vec<Part>) -> Result<()> {
+        let chat = self.client.get_mod::<mods::Chat>();
+        let message_type = match &outgoing.to {
+            RoomId::User(_) => MessageType::Chat,
+            RoomId::Room(_) => MessageType::Groupchat,
+        };
+
+        for Part { id, body } in parts {
+            let fallback = message_styling_fallback(&body);
+            let is_first = id == outgoing.id;
+
+            let mut message = Message::new()
+                .set_id(id.clone().into())
+                .set_type(message_type.clone())
+                .set_to(outgoing.to.clone())
+                .add_content("text/markdown", body)
+                .set_body(fallback)
+                .set_store(true)
+                .add_payload(
+                    Element::builder("origin-id", SID_NS)
+                        .attr("id", &id)
+                        .build(),
+                );
 
-                let parser = MarkdownParser::new(&body);
-                let fallback = parser.convert_to_message_styling();
+            if let Some(replaces) = outgoing.replaces.as_ref().filter(|_| is_first) {
+                message = message.set_replace(replaces.clone().into());
+            }
+
+            if let Some(reply_to) = outgoing.reply_to.as_ref().filter(|_| is_first) {
+                match self.own_jid(&outgoing.to) {
+                    Ok(own_jid) => {
+                        message = message.set_reply(Reply {
+                            id: Some(reply_to.clone()),
+                            to: Some(own_jid),
+                        })
+                    }
+                    Err(err) => warn!("Sending message {id} without reply: {err}"),
+                }
+            }
+
+            if let Some(thread) = &outgoing.thread {
+                message = message.set_thread(thread.clone().into());
+            }
 
-                let message = Message::new()
-                    .set_id(UUIDProvider::new().new_id().into())
-                    .set_type(message_type)
-                    .set_to(to)
-                    .add_content("text/markdown", body)
-                    .set_body(fallback)
-                    .set_store(true);
+            // Rooms confirm messages by reflecting them, users with receipts.
+            if let RoomId::User(_) = outgoing.to {
+                message = message.add_payload(Element::builder("request", RECEIPTS_NS).build());
+            }
 
-                chat.send_raw_message(message, false)?;
+            if let Err(err) = chat.send_raw_message(message, false) {
+                self.deliveries.rejected(&id, err.to_string());
+                return Err(err.into());
             }
         }
         Ok(())
     }
 
-    async fn connect_if_needed(&self) -> Result<()> {
+    /// Returns the JID our messages to `to` are sent from, i.e. our occupant JID in rooms.
+    fn own_jid(&self, to: &RoomId) -> Result<Jid> {
+        let jid = match to {
+            RoomId::User(_) => self.config.jid.with_resource_str(&self.config.resource)?,
+            RoomId::Room(room) => self.rooms.occupant_jid(room)?,
+        };
+        Ok(jid.into())
+    }
+
+    /// Publishes the image at `path` as our avatar (XEP-0084).
+    async fn publish_avatar(&self, path: &Path) -> Result<()> {
+        let mime_type = avatar_mime_type(path)
+            .ok_or_else(|| format_err!("Avatars must be PNG, JPEG, GIF or WebP images"))?;
+        let image = std::fs::read(path)?;
+        let checksum = ImageId::from(
+            Sha1::digest(&image)
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect::<String>(),
+        );
+
+        let profile = self.client.get_mod::<mods::Profile>();
+        profile
+            .set_avatar_image(&checksum, BASE64_STANDARD.encode(&image))
+            .await?;
+        profile
+            .set_avatar_metadata(image.len(), &checksum, mime_type, None, None)
+            .await?;
+        info!("Published avatar {}.", path.display());
+
+        Ok(())
+    }
+
+    async fn connect_if_needed(&mut self) -> Result<()> {
         if self.is_connected.load(Ordering::Acquire) {
             return Ok(());
         }
 
-        let jid = self
-            .config
-            .jid
-            .with_resource_str("bot")
-            .expect("Failed to append resource string to jid");
+        let jid = self.config.jid.with_resource_str(&self.config.resource)?;
 
         info!("Connecting as {jid}…");
         self.client
@@ -148,24 +461,19 @@ impl XMPPService {
         self.client.get_mod::<mods::Status>().send_presence(
             None,
             Some(Show::Chat),
-            None,
+            self.config.status.as_deref(),
             None,
             None,
         )?;
 
-        let muc = self.client.get_mod::<mods::MUC>();
-        for room in &self.rooms {
-            info!("Entering room {room}…");
-            muc.enter_room(
-                &room.with_resource_str("bot").unwrap(),
-                None,
-                None,
-                Some(Show::Chat),
-                None,
-            )
-            .await?;
+        if let Some(avatar) = &self.config.avatar {
+            if let Err(err) = self.publish_avatar(avatar).await {
+                warn!("Failed to publish avatar {}: {err}", avatar.display());
+            }
         }
 
+        self.rooms.enter_all(&self.client);
+
         let chat = self.client.get_mod::<mods::Chat>();
         chat.send_message(
             Jid::new("marc@prose.org").unwrap(),
@@ -178,10 +486,75 @@ impl XMPPService {
     }
 }
 
-fn handle_event(_client: Client, event: Event, is_connected: &AtomicBool) {
+impl RoomConnector for Client {
+    async fn enter_room(
+        &self,
+        occupant: &FullJid,
+        password: Option<&str>,
+        status: Option<&str>,
+    ) -> Result<()> {
+        self.get_mod::<mods::MUC>()
+            .enter_room(occupant, password, None, Some(Show::Chat), status)
+            .await?;
+        Ok(())
+    }
+
+    fn exit_room(&self, occupant: &FullJid) -> Result<()> {
+        self.get_mod::<mods::MUC>().exit_room(occupant)?;
+        Ok(())
+    }
+}
+
+/// Returns the MIME type of the avatar image at `path` based on its extension.
+fn avatar_mime_type(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    match extension.as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "gif" => Some("image/gif"),
+        "webp" => Some("image/webp"),
+        _ => None,
+    }
+}
+
+/// Converts `markdown` into the XEP-0393 message styling sent as the body of a message, for
+/// clients that don't render markdown.
+pub fn message_styling_fallback(markdown: &str) -> String {
+    MarkdownParser::new(markdown).convert_to_message_styling()
+}
+
+/// The state shared with the event handler of the client.
+#[derive(Clone)]
+struct EventHandlerState {
+    is_connected: Arc<AtomicBool>,
+    /// The occupants of the rooms we're in, by occupant JID (i.e. room@service/nick).
+    occupants: Arc<Mutex<HashMap<FullJid, Occupant>>>,
+    message_handler: Arc<RwLock<Option<Arc<dyn MessageHandler>>>>,
+    rooms: Rooms,
+    deliveries: Deliveries,
+    /// The users whose invitations to rooms are accepted.
+    accept_invitations_from: Vec<BareJid>,
+}
+
+struct Occupant {
+    /// The real JID of the occupant, unless the room is anonymous.
+    jid: Option<BareJid>,
+    affiliation: Affiliation,
+}
+
+fn handle_event(client: Client, event: Event, state: &EventHandlerState) {
     match event {
-        Event::Client(event) => handle_client_event(event, is_connected),
-        Event::Chat(ChatEvent::Message(message)) => handle_received_message(message),
+        Event::Client(event) => handle_client_event(event, &state.is_connected),
+        Event::Chat(ChatEvent::Message(message)) => {
+            handle_received_message(&client, message, state)
+        }
+        Event::Status(StatusEvent::Presence(presence)) => handle_presence(&client, presence, state),
+        Event::MUC(MucEvent::DirectInvite { from, invite }) => {
+            handle_direct_invitation(&client, from, invite, state)
+        }
+        Event::MUC(MucEvent::MediatedInvite { from, invite }) => {
+            handle_mediated_invitation(&client, from, invite, state)
+        }
         _ => (),
     }
 }
@@ -207,7 +580,7 @@ fn handle_client_event(event: ClientEvent, is_connected: &AtomicBool) {
     }
 }
 
-fn handle_received_message(message: Message) {
+fn handle_received_message(client: &Client, message: Message, state: &EventHandlerState) {
     info!(
         "Received message from {}: {}",
         message
@@ -217,4 +590,403 @@ fn handle_received_message(message: Message) {
             .unwrap_or_else(|| "<unknown>".to_string()),
         message.body().unwrap_or("<no body>")
     );
+
+    // Rooms send their history when we enter them. Messages in there have been handled before.
+    if message.delay.is_some() {
+        return;
+    }
+
+    if message.type_ == MessageType::Error {
+        if let Some(id) = &message.id {
+            state
+                .deliveries
+                .rejected(&id.to_string(), "The message was rejected".to_string());
+        }
+        return;
+    }
+
+    if handle_confirmation(&message, &state.deliveries, &state.rooms) {
+        return;
+    }
+
+    let (Some(from), Some(body)) = (message.from.as_ref(), message.body()) else {
+        return;
+    };
+
+    let incoming = match message.type_ {
+        MessageType::Groupchat => {
+            let Ok(occupant_jid) = from.try_as_full() else {
+                return;
+            };
+
+            let room = from.to_bare();
+            let addressed_body = strip_addressing(body, state.rooms.nickname(&room));
+            let occupants = state.occupants.lock().unwrap();
+            let occupant = occupants.get(occupant_jid);
+            IncomingMessage {
+                room: RoomId::Room(room),
+                sender: occupant.and_then(|occupant| occupant.jid.clone()),
+                affiliation: occupant.map(|occupant| occupant.affiliation),
+                is_addressed: addressed_body.is_some(),
+                body: addressed_body.unwrap_or(body).to_string(),
+            }
+        }
+        MessageType::Chat => IncomingMessage {
+            room: RoomId::User(from.to_bare()),
+            sender: Some(from.to_bare()),
+            affiliation: None,
+            is_addressed: true,
+            body: body.to_string(),
+        },
+        _ => return,
+    };
+
+    let handler = state.message_handler.read().unwrap().clone();
+    let Some(reply) = handler.and_then(|handler| handler.handle_message(incoming.clone())) else {
+        return;
+    };
+
+    let message_type = match &incoming.room {
+        RoomId::User(_) => MessageType::Chat,
+        RoomId::Room(_) => MessageType::Groupchat,
+    };
+    let chat = client.get_mod::<mods::Chat>();
+    if let Err(err) = chat.send_message(incoming.room.into(), &reply, &message_type, None) {
+        warn!("Failed to reply to message: {err}");
+    }
+}
+
+/// Keeps track of the occupants of the rooms we're in and their affiliation, so that permissions
+/// can be checked when they send commands. Rejoins rooms we were removed from.
+fn handle_presence(client: &Client, presence: Presence, state: &EventHandlerState) {
+    let Some(from) = presence.from.as_ref() else {
+        return;
+    };
+
+    // Errors are reported for the room or our occupant JID. Rooms we're not in ignore them.
+    if presence.type_ == PresenceType::Error {
+        let room = from.to_bare();
+        let condition = error_condition(&presence);
+        let reason = room_error_reason(&room, condition.as_deref(), &state.rooms);
+        if condition.is_some_and(|condition| TERMINAL_ROOM_ERRORS.contains(&condition.as_str())) {
+            state.rooms.room_failed(&room, reason);
+        } else {
+            state.rooms.room_left(client, &room, reason);
+        }
+        return;
+    }
+
+    let Ok(occupant_jid) = from.try_as_full().cloned() else {
+        return;
+    };
+    let Some(muc_user) = presence
+        .payloads
+        .iter()
+        .find_map(|payload| MucUser::try_from(payload.clone()).ok())
+    else {
+        return;
+    };
+
+    let is_own_presence =
+        occupant_jid.resource().as_str() == state.rooms.nickname(&occupant_jid.to_bare());
+    if is_own_presence && presence.type_ == PresenceType::Unavailable {
+        let room = occupant_jid.to_bare();
+        if muc_user.status.contains(&MucStatus::Banned) {
+            state
+                .rooms
+                .room_failed(&room, "Banned from the room".to_string());
+            return;
+        }
+
+        let reason = if muc_user.status.contains(&MucStatus::Kicked) {
+            "Kicked from the room"
+        } else if muc_user.status.contains(&MucStatus::ServiceShutdown) {
+            "The MUC service shut down"
+        } else if muc_user.status.contains(&MucStatus::RemovalFromRoom) {
+            "Removed from the room"
+        } else {
+            "Removed from the room or the room was destroyed"
+        };
+        state.rooms.room_left(client, &room, reason.to_string());
+    }
+
+    let Some(item) = muc_user.items.into_iter().next() else {
+        return;
+    };
+
+    let mut occupants = state.occupants.lock().unwrap();
+
+    if presence.type_ == PresenceType::Unavailable {
+        occupants.remove(&occupant_jid);
+        return;
+    }
+
+    let affiliation = match item.affiliation {
+        MucAffiliation::Owner => Affiliation::Owner,
+        MucAffiliation::Admin => Affiliation::Admin,
+        MucAffiliation::Member => Affiliation::Member,
+        MucAffiliation::Outcast => Affiliation::Outcast,
+        MucAffiliation::None => Affiliation::None,
+    };
+    occupants.insert(
+        occupant_jid,
+        Occupant {
+            jid: item.jid.map(|jid| jid.into_bare()),
+            affiliation,
+        },
+    );
+}
+
+/// Returns the rest of the room message `body` if it is addressed to `nickname`, e.g. `!help` for
+/// `GitHub: !help`.
+fn strip_addressing<'a>(body: &'a str, nickname: &str) -> Option<&'a str> {
+    let rest = body.trim_start().strip_prefix(nickname)?;
+    rest.strip_prefix([':', ',']).map(str::trim_start)
+}
+
+/// Confirms our message that `message` acknowledges with a receipt (XEP-0184), or reflects if we
+/// sent it to a room. Returns `true` if `message` was such a confirmation, i.e. needs no further
+/// handling.
+fn handle_confirmation(message: &Message, deliveries: &Deliveries, rooms: &Rooms) -> bool {
+    if let Some(id) = receipt_id(message) {
+        deliveries.confirmed(&id);
+        return true;
+    }
+
+    if message.type_ != MessageType::Groupchat {
+        return false;
+    }
+    let Some(occupant_jid) = message
+        .from
+        .as_ref()
+        .and_then(|from| from.try_as_full().ok())
+    else {
+        return false;
+    };
+    if occupant_jid.resource().as_str() != rooms.nickname(&occupant_jid.to_bare()) {
+        return false;
+    }
+
+    // Rooms may assign their own ids to reflections, but keep our origin id.
+    if let Some(id) = origin_id(message).or_else(|| message.id.as_ref().map(ToString::to_string)) {
+        deliveries.confirmed(&id);
+    }
+    true
+}
+
+/// Returns the origin id (XEP-0359) the sender assigned to `message`, if any.
+fn origin_id(message: &Message) -> Option<String> {
+    message
+        .payloads
+        .iter()
+        .find(|payload| payload.is("origin-id", SID_NS))?
+        .attr("id")
+        .map(ToString::to_string)
+}
+
+/// Returns the id of the message a receipt (XEP-0184) in `message` acknowledges, if any.
+fn receipt_id(message: &Message) -> Option<String> {
+    message
+        .payloads
+        .iter()
+        .find(|payload| payload.is("received", RECEIPTS_NS))?
+        .attr("id")
+        .map(ToString::to_string)
+}
+
+/// Describes the error with the defined `condition` that `room` reported and logs how to fix it if
+/// it is caused by our configuration.
+fn room_error_reason(room: &BareJid, condition: Option<&str>, rooms: &Rooms) -> String {
+    match condition {
+        Some("registration-required") => {
+            let occupant = rooms
+                .occupant_jid(room)
+                .map_or(room.to_string(), |jid| jid.to_string());
+            error!("Room {room} is members-only. Ask an owner to make {occupant} a member.");
+            "The room is members-only".to_string()
+        }
+        Some("not-authorized") => {
+            error!("Room {room} requires a password. Set it in `xmpp.rooms`.");
+            "The password is missing or wrong".to_string()
+        }
+        Some("forbidden") => "Banned from the room".to_string(),
+        Some(condition) => format!("The room reported an error: {condition}"),
+        None => "The room reported an error".to_string(),
+    }
+}
+
+/// Returns the defined condition of the error in `presence`, e.g. "registration-required".
+fn error_condition(presence: &Presence) -> Option<String> {
+    presence
+        .payloads
+        .iter()
+        .find(|payload| payload.name() == "error")?
+        .children()
+        .find(|child| child.ns() == STANZA_ERROR_NS && child.name() != "text")
+        .map(|condition| condition.name().to_string())
+}
+
+/// Enters the room of a direct invitation (XEP-0249) sent by one of `accept_invitations_from`.
+/// The sender is the `from` of the stanza, which the servers vouch for.
+fn handle_direct_invitation(
+    client: &Client,
+    from: Jid,
+    invite: DirectInvite,
+    state: &EventHandlerState,
+) {
+    let room = invite.jid;
+    let inviter = from.to_bare();
+    if !state.accept_invitations_from.contains(&inviter) {
+        info!("Ignoring invitation to {room} from {inviter}.");
+        return;
+    }
+
+    info!("Accepting invitation to {room} from {inviter}.");
+    state.rooms.accept_invitation(client, room, invite.password);
+}
+
+/// Enters the room of a mediated invitation (XEP-0045) from one of `accept_invitations_from`.
+/// The inviter is named by the room, i.e. anyone can claim to be an admin from a room on their
+/// own server. Such invitations are only trusted from the MUC services we're in rooms of.
+fn handle_mediated_invitation(
+    client: &Client,
+    room: Jid,
+    invite: MediatedInvite,
+    state: &EventHandlerState,
+) {
+    let room = room.to_bare();
+    if !state.rooms.is_known_service(&room) {
+        info!("Ignoring invitation to {room} on an unknown MUC service.");
+        return;
+    }
+
+    let inviter = invite
+        .invites
+        .iter()
+        .find_map(|invite| invite.from.as_ref())
+        .map(|jid| jid.to_bare());
+    let Some(inviter) = inviter.filter(|jid| state.accept_invitations_from.contains(jid)) else {
+        info!("Ignoring invitation to {room}.");
+        return;
+    };
+
+    info!("Accepting invitation to {room} from {inviter}.");
+    state.rooms.accept_invitation(client, room, invite.password);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::RoomSettings;
+    use crate::services::xmpp_service::DeliveryStatus;
+
+    use super::*;
+
+    fn room() -> BareJid {
+        "room@example.org".parse().unwrap()
+    }
+
+    fn rooms() -> Rooms {
+        Rooms::new(vec![RoomSettings {
+            room: room(),
+            password: None,
+            nickname: Some("GitHub".to_string()),
+        }])
+    }
+
+    fn deliveries(to: RoomId) -> Deliveries {
+        let deliveries = Deliveries::new();
+        deliveries.sent(
+            &OutgoingMessage::with_id("msg1", to, "Hello"),
+            vec![Part {
+                id: "msg1".to_string(),
+                body: "Hello".to_string(),
+            }],
+        );
+        deliveries
+    }
+
+    fn status(deliveries: &Deliveries) -> DeliveryStatus {
+        deliveries.list()[0].status.clone()
+    }
+
+    #[test]
+    fn test_receipts_confirm_messages() {
+        let deliveries = deliveries(RoomId::User("admin@example.org".parse().unwrap()));
+        let receipt = Message::new()
+            .set_type(MessageType::Chat)
+            .set_from("admin@example.org/phone".parse::<Jid>().unwrap())
+            .add_payload(
+                Element::builder("received", RECEIPTS_NS)
+                    .attr("id", "msg1")
+                    .build(),
+            );
+
+        assert!(handle_confirmation(&receipt, &deliveries, &rooms()));
+        assert_eq!(status(&deliveries), DeliveryStatus::Delivered);
+    }
+
+    #[test]
+    fn test_reflections_of_own_room_messages_confirm_them() {
+        let deliveries = deliveries(RoomId::Room(room()));
+        let reflection = |nickname: &str| {
+            Message::new()
+                .set_id("msg1".to_string().into())
+                .set_type(MessageType::Groupchat)
+                .set_from(room().with_resource_str(nickname).unwrap())
+                .set_body("Hello")
+        };
+
+        assert!(!handle_confirmation(
+            &reflection("bot"),
+            &deliveries,
+            &rooms()
+        ));
+        assert_eq!(status(&deliveries), DeliveryStatus::Pending);
+
+        assert!(handle_confirmation(
+            &reflection("GitHub"),
+            &deliveries,
+            &rooms()
+        ));
+        assert_eq!(status(&deliveries), DeliveryStatus::Delivered);
+    }
+
+    #[test]
+    fn test_reflections_are_matched_by_origin_id() {
+        let deliveries = deliveries(RoomId::Room(room()));
+        let reflection = Message::new()
+            .set_id("assigned-by-room".to_string().into())
+            .set_type(MessageType::Groupchat)
+            .set_from(room().with_resource_str("GitHub").unwrap())
+            .set_body("Hello")
+            .add_payload(
+                Element::builder("origin-id", SID_NS)
+                    .attr("id", "msg1")
+                    .build(),
+            );
+
+        assert!(handle_confirmation(&reflection, &deliveries, &rooms()));
+        assert_eq!(status(&deliveries), DeliveryStatus::Delivered);
+    }
+
+    #[test]
+    fn test_strip_addressing() {
+        assert_eq!(strip_addressing("GitHub: !help", "GitHub"), Some("!help"));
+        assert_eq!(strip_addressing(" GitHub,!help", "GitHub"), Some("!help"));
+        assert_eq!(strip_addressing("GitHubber: !help", "GitHub"), None);
+        assert_eq!(strip_addressing("!help", "GitHub"), None);
+    }
+
+    #[test]
+    fn test_regular_messages_confirm_nothing() {
+        let deliveries = deliveries(RoomId::User("admin@example.org".parse().unwrap()));
+        let message = Message::new()
+            .set_id("msg1".to_string().into())
+            .set_type(MessageType::Chat)
+            .set_from("admin@example.org/phone".parse::<Jid>().unwrap())
+            .set_body("!status");
+
+        assert!(!handle_confirmation(&message, &deliveries, &rooms()));
+        assert_eq!(status(&deliveries), DeliveryStatus::Pending);
+    }
 }
@@ -1,4 +1,8 @@
-use prose_xmpp::BareJid;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use prose_xmpp::{BareJid, IDProvider, UUIDProvider};
+use serde::Serialize;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum RoomId {
@@ -6,6 +10,179 @@ pub enum RoomId {
     Room(BareJid),
 }
 
+impl RoomId {
+    pub fn jid(&self) -> &BareJid {
+        match self {
+            RoomId::User(jid) | RoomId::Room(jid) => jid,
+        }
+    }
+}
+
+/// A markdown message to send to a room or user.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutgoingMessage {
+    pub to: RoomId,
+    /// The id of the message. Long messages are split and only the first part carries this id.
+    pub id: String,
+    pub body: String,
+    /// The id of an earlier message this message corrects (XEP-0308).
+    pub replaces: Option<String>,
+    /// The id of an earlier message this message replies to (XEP-0461).
+    pub reply_to: Option<String>,
+    /// The thread the message belongs to, so that clients can group related messages.
+    pub thread: Option<String>,
+}
+
+impl OutgoingMessage {
+    pub fn new(to: RoomId, body: impl Into<String>) -> Self {
+        Self::with_id(new_message_id(), to, body)
+    }
+
+    pub fn with_id(id: impl Into<String>, to: RoomId, body: impl Into<String>) -> Self {
+        Self {
+            to,
+            id: id.into(),
+            body: body.into(),
+            replaces: None,
+            reply_to: None,
+            thread: None,
+        }
+    }
+
+    /// Turns the message into a correction of the message with id `id`.
+    pub fn replacing(mut self, id: impl Into<String>) -> Self {
+        self.replaces = Some(id.into());
+        self
+    }
+
+    /// Turns the message into a reply to the message with id `id`.
+    pub fn replying_to(mut self, id: impl Into<String>) -> Self {
+        self.reply_to = Some(id.into());
+        self
+    }
+
+    pub fn in_thread(mut self, thread: impl Into<String>) -> Self {
+        self.thread = Some(thread.into());
+        self
+    }
+}
+
+/// Generates a new, unique message id.
+pub fn new_message_id() -> String {
+    UUIDProvider::new().new_id()
+}
+
+/// A message the bot received in a room or directly.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IncomingMessage {
+    /// Where the message was sent, i.e. where replies go.
+    pub room: RoomId,
+    /// The real JID of the sender, if known. It is unknown in anonymous rooms.
+    pub sender: Option<BareJid>,
+    /// The affiliation of the sender with the room, if the message was sent in a room.
+    pub affiliation: Option<Affiliation>,
+    /// Whether the message is meant for the bot, i.e. sent directly or addressed to the bot's
+    /// nickname in a room, e.g. `GitHub: !help`. The addressing isn't part of `body`.
+    pub is_addressed: bool,
+    pub body: String,
+}
+
+/// The affiliation of a user with a room (XEP-0045).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Affiliation {
+    Owner,
+    Admin,
+    Member,
+    Outcast,
+    None,
+}
+
+/// Whether the bot is in a room it's supposed to be in.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum RoomState {
+    Joining,
+    Joined,
+    /// Waiting to enter the room again after failing to enter it or being removed from it.
+    Rejoining {
+        reason: String,
+        /// The number of failed attempts to enter the room since, starting at 0.
+        attempt: u32,
+    },
+    /// The room refused the bot for good, e.g. because it was banned or isn't a member of a
+    /// members-only room. It isn't entered again until the bot is invited to it or the room is
+    /// listed again.
+    Failed {
+        reason: String,
+    },
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct RoomStatus {
+    pub room: BareJid,
+    #[serde(flatten)]
+    pub state: RoomState,
+}
+
+/// Whether a message we sent was confirmed by its recipient.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Delivery {
+    pub id: String,
+    pub to: BareJid,
+    #[serde(flatten)]
+    pub status: DeliveryStatus,
+    /// How often the message was sent.
+    pub attempts: u32,
+    pub sent_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    /// Waiting for a receipt (XEP-0184) or the reflection of a room message.
+    Pending,
+    Delivered,
+    /// The message was sent to a user whose client didn't confirm it in time, e.g. because they
+    /// are offline. It isn't sent again, since the server keeps it for them.
+    Unconfirmed,
+    /// The message was rejected and will be sent again.
+    Rejected {
+        reason: String,
+    },
+    /// The message wasn't confirmed after being sent repeatedly.
+    Failed {
+        reason: String,
+    },
+}
+
+/// Handles the messages the bot receives.
+pub trait MessageHandler: Send + Sync {
+    /// Returns the reply to `message`, if any.
+    fn handle_message(&self, message: IncomingMessage) -> Option<String>;
+}
+
 pub trait XMPPService: Send + Sync {
-    fn send_message(&self, to: RoomId, message: String);
+    fn send_message(&self, message: OutgoingMessage);
+
+    /// Sets the handler for received messages. Services that don't receive messages ignore it.
+    fn set_message_handler(&self, _handler: Arc<dyn MessageHandler>) {}
+
+    /// Sets the rooms to be in, i.e. enters the new rooms and leaves the ones no longer listed.
+    fn set_rooms(&self, _rooms: Vec<BareJid>) {}
+
+    /// Leaves `room` the bot entered because it was invited to it. Returns `false` if it wasn't
+    /// invited to `room`.
+    fn leave_room(&self, _room: BareJid) -> bool {
+        false
+    }
+
+    /// Returns whether the bot is in each of the rooms it's supposed to be in.
+    fn room_states(&self) -> Vec<RoomStatus> {
+        vec![]
+    }
+
+    /// Returns whether the recently sent messages were confirmed, most recent first.
+    fn deliveries(&self) -> Vec<Delivery> {
+        vec![]
+    }
 }
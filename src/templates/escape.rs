@@ -0,0 +1,188 @@
+use std::fmt::Write;
+
+use minijinja::value::Value;
+use minijinja::{AutoEscape, Error, Output, State};
+
+/// The name of the auto escape mode used for markdown templates.
+const MARKDOWN: &str = "markdown";
+
+/// Characters that have a special meaning in markdown and are escaped by `escape_markdown`.
+const MARKDOWN_SPECIAL_CHARS: &[char] = &[
+    '\\', '`', '*', '_', '[', ']', '(', ')', '<', '>', '~', '#', '|',
+];
+/// Characters that start a list item or a horizontal rule at the beginning of a line. Ordered list
+/// items start with a number followed by `.` or `)` and a space instead.
+const LINE_START_MARKERS: &[char] = &['-', '+'];
+/// Characters that are percent-encoded in URLs by `escape_url`, since they could end a markdown
+/// link target or start a link.
+const URL_SPECIAL_CHARS: &[char] = &['\\', '`', '[', ']', '(', ')', '<', '>'];
+
+/// Enables markdown escaping for all `.md` templates, including repository-specific overrides.
+pub fn auto_escape_callback(name: &str) -> AutoEscape {
+    if name.ends_with(".md") {
+        AutoEscape::Custom(MARKDOWN)
+    } else {
+        AutoEscape::None
+    }
+}
+
+/// Writes `value` to `out`, escaping markdown in templates with markdown auto escaping enabled.
+/// Values marked as safe (e.g. via the `safe` or `url` filter) are written verbatim.
+pub fn format(out: &mut Output<'_>, state: &State<'_, '_>, value: &Value) -> Result<(), Error> {
+    if !matches!(state.auto_escape(), AutoEscape::Custom(MARKDOWN)) || value.is_safe() {
+        return write!(out, "{value}").map_err(Error::from);
+    }
+
+    out.write_str(&escape_markdown(&value.to_string()))
+        .map_err(Error::from)
+}
+
+/// Escapes characters with a special meaning in markdown, so that `value` is displayed verbatim.
+/// List markers are escaped at the beginning of lines only, e.g. `- item` or `1. item`, so that
+/// e.g. version numbers stay readable.
+pub fn escape_markdown(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for (idx, line) in value.split('\n').enumerate() {
+        if idx > 0 {
+            escaped.push('\n');
+        }
+
+        let indent = line.len() - line.trim_start().len();
+        let digits = line[indent..]
+            .bytes()
+            .take_while(u8::is_ascii_digit)
+            .count();
+        let is_ordered_list_item = digits > 0
+            && line[indent + digits..].starts_with('.')
+            && line[indent + digits + 1..]
+                .chars()
+                .next()
+                .map_or(true, char::is_whitespace);
+
+        for (pos, c) in line.char_indices() {
+            let is_list_marker = (pos == indent && LINE_START_MARKERS.contains(&c))
+                || (is_ordered_list_item && pos == indent + digits);
+            if is_list_marker || MARKDOWN_SPECIAL_CHARS.contains(&c) {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
+    }
+
+    escaped
+}
+
+/// Percent-encodes `URL_SPECIAL_CHARS` and whitespace in the URL `value`, so that it can be used
+/// as a link target. Returns `None` if `value` isn't an http(s) or mailto URL, e.g. a
+/// `javascript:` URL, which must not be used as a link target.
+pub fn escape_url(value: &str) -> Option<String> {
+    let is_url = ["https://", "http://", "mailto:"]
+        .iter()
+        .any(|scheme| value.starts_with(scheme));
+    if !is_url {
+        return None;
+    }
+
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if !URL_SPECIAL_CHARS.contains(&c) && !c.is_whitespace() {
+            escaped.push(c);
+            continue;
+        }
+        for byte in c.to_string().bytes() {
+            write!(escaped, "%{byte:02X}").unwrap();
+        }
+    }
+    Some(escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use minijinja::{context, Environment};
+
+    use super::*;
+
+    fn render(name: &str, source: &str, title: &str) -> String {
+        let mut env = Environment::new();
+        env.set_auto_escape_callback(auto_escape_callback);
+        env.set_formatter(format);
+        env.add_template(name, source).unwrap();
+        env.get_template(name)
+            .unwrap()
+            .render(context!(title => title))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_escapes_markdown_templates() {
+        assert_eq!(
+            render(
+                "issues__opened.md",
+                "**Title**: {{ title }}",
+                "Fix [this](https://evil.com) `now`"
+            ),
+            "**Title**: Fix \\[this\\]\\(https://evil.com\\) \\`now\\`"
+        );
+    }
+
+    #[test]
+    fn test_safe_values_are_not_escaped() {
+        assert_eq!(
+            render("issues__opened.md", "{{ title | safe }}", "**bold**"),
+            "**bold**"
+        );
+    }
+
+    #[test]
+    fn test_url_like_values_are_escaped() {
+        assert_eq!(
+            render(
+                "issues__opened.md",
+                "{{ title }}",
+                "https://x](https://evil.com)"
+            ),
+            "https://x\\]\\(https://evil.com\\)"
+        );
+    }
+
+    #[test]
+    fn test_escapes_headings_tables_and_link_targets() {
+        assert_eq!(escape_markdown("# Title"), "\\# Title");
+        assert_eq!(escape_markdown("a | b"), "a \\| b");
+        assert_eq!(escape_markdown("(https://x)"), "\\(https://x\\)");
+    }
+
+    #[test]
+    fn test_escapes_list_markers_at_line_start() {
+        assert_eq!(escape_markdown("- item\n  + item"), "\\- item\n  \\+ item");
+        assert_eq!(
+            escape_markdown("1. item\n12.\n1.2"),
+            "1\\. item\n12\\.\n1.2"
+        );
+        assert_eq!(escape_markdown("2) item"), "2\\) item");
+        assert_eq!(escape_markdown("---"), "\\---");
+        assert_eq!(
+            escape_markdown("a-b + c, version 1.2."),
+            "a-b + c, version 1.2."
+        );
+    }
+
+    #[test]
+    fn test_escape_url() {
+        assert_eq!(
+            escape_url("https://example.org/a_(b)").as_deref(),
+            Some("https://example.org/a_%28b%29")
+        );
+        assert_eq!(
+            escape_url("https://x](https://evil.com) [y]").as_deref(),
+            Some("https://x%5D%28https://evil.com%29%20%5By%5D")
+        );
+        assert_eq!(escape_url("javascript:alert(1)"), None);
+    }
+
+    #[test]
+    fn test_other_templates_are_not_escaped() {
+        assert_eq!(render("issues__opened.txt", "{{ title }}", "a_b"), "a_b");
+    }
+}
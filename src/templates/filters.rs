@@ -0,0 +1,601 @@
+use chrono::{DateTime, Datelike, Utc};
+use minijinja::value::Value;
+use minijinja::{Error, ErrorKind, State};
+
+use super::escape::{escape_markdown, escape_url};
+use super::locale::Locale;
+
+/// Shown by `duration` if a timestamp is missing.
+const UNKNOWN_DURATION: &str = "–";
+/// Shown by `author_name` if the author has neither a username, a name nor an email address.
+const UNKNOWN_AUTHOR: &str = "unknown";
+
+/// Shortens a commit hash to `length` (defaults to 7) characters.
+pub fn short_sha(value: &str, length: Option<usize>) -> String {
+    value.chars().take(length.unwrap_or(7)).collect()
+}
+
+/// Returns the branch or tag name of a git ref, e.g. `feature/x` for `refs/heads/feature/x`.
+pub fn branch_name(value: &str) -> String {
+    value
+        .strip_prefix("refs/heads/")
+        .or_else(|| value.strip_prefix("refs/tags/"))
+        .unwrap_or(value)
+        .to_string()
+}
+
+/// Returns the first line of `value`, e.g. the subject of a commit message.
+pub fn first_line(value: &str) -> String {
+    value.lines().next().unwrap_or_default().to_string()
+}
+
+/// Keeps the first `count` lines of `value` and appends an ellipsis if lines were removed.
+pub fn truncate_lines(value: &str, count: usize) -> String {
+    let lines = value.lines().collect::<Vec<_>>();
+
+    if lines.len() <= count {
+        return lines.join("\n");
+    }
+
+    lines[..count].join("\n") + "\n…"
+}
+
+/// Formats an RFC 3339 timestamp relative to now in the message's locale, e.g. `5 minutes ago`.
+pub fn relative_time(state: &State<'_, '_>, value: &str) -> Result<String, Error> {
+    let seconds = (Utc::now() - parse_timestamp(value)?).num_seconds();
+    Ok(format_relative_time(Locale::from_state(state), seconds))
+}
+
+/// Formats the date of an RFC 3339 timestamp in the message's locale, e.g. `June 27, 2024`.
+pub fn date(state: &State<'_, '_>, value: &str) -> Result<String, Error> {
+    Ok(format_date(
+        Locale::from_state(state),
+        parse_timestamp(value)?,
+    ))
+}
+
+/// Formats a duration as e.g. `1h 2m 3s`. The duration is either given in seconds or as an RFC 3339
+/// start timestamp along with the end timestamp as argument, e.g.
+/// `run.run_started_at | duration(run.updated_at)`. Returns "–" if a timestamp is missing, e.g.
+/// because the run never started.
+pub fn duration(value: Value, end: Option<&str>) -> Result<String, Error> {
+    if value.is_undefined() || value.is_none() {
+        return Ok(UNKNOWN_DURATION.to_string());
+    }
+
+    let seconds = match (value.as_str(), end) {
+        (Some(start), Some(end)) => (parse_timestamp(end)? - parse_timestamp(start)?).num_seconds(),
+        (Some(_), None) => return Ok(UNKNOWN_DURATION.to_string()),
+        (None, _) => i64::try_from(value)?,
+    };
+    Ok(format_duration(seconds))
+}
+
+/// Returns the name of a commit author: the GitHub username if the author has an account, their
+/// git name or email address otherwise.
+pub fn author_name(author: Value) -> Result<String, Error> {
+    if author.is_undefined() || author.is_none() {
+        return Ok(UNKNOWN_AUTHOR.to_string());
+    }
+
+    for key in ["username", "name", "email"] {
+        if let Some(name) = author
+            .get_attr(key)?
+            .as_str()
+            .filter(|name| !name.is_empty())
+        {
+            return Ok(name.to_string());
+        }
+    }
+    Ok(UNKNOWN_AUTHOR.to_string())
+}
+
+/// Returns the emoji for the severity of a security alert, e.g. 🔴 for `high`. Covers the levels
+/// of Dependabot, code scanning and vulnerability alerts.
+pub fn severity_emoji(severity: Option<&str>) -> &'static str {
+    match severity.map(str::to_ascii_lowercase).as_deref() {
+        Some("critical") => "🚨",
+        Some("high" | "error") => "🔴",
+        Some("moderate" | "medium" | "warning") => "🟠",
+        Some("low" | "note") => "🟡",
+        _ => "⚪",
+    }
+}
+
+/// Returns the emoji for the conclusion of a workflow run, e.g. 🟢 for `success`.
+pub fn conclusion_emoji(conclusion: Option<&str>) -> &'static str {
+    match conclusion {
+        Some("success") => "🟢",
+        Some("neutral" | "skipped" | "cancelled") => "⚪",
+        _ => "🔴",
+    }
+}
+
+/// Returns the first `count` items of `value`. Returns `value` unchanged if `count` is undefined,
+/// so that templates can pass `max_items` along without checking whether it is set.
+pub fn limit(value: Value, count: Option<usize>) -> Result<Value, Error> {
+    let Some(count) = count else {
+        return Ok(value);
+    };
+    Ok(Value::from(
+        value.try_iter()?.take(count).collect::<Vec<_>>(),
+    ))
+}
+
+/// Returns `count` followed by the singular or plural form of a word, e.g. `3 commits`, following
+/// the pluralization rules of the message's locale. The plural defaults to the singular with an
+/// appended "s".
+pub fn pluralize(
+    state: &State<'_, '_>,
+    count: i64,
+    singular: &str,
+    plural: Option<&str>,
+) -> String {
+    pluralize_in(Locale::from_state(state), count, singular, plural)
+}
+
+fn pluralize_in(locale: Locale, count: i64, singular: &str, plural: Option<&str>) -> String {
+    if locale.is_singular(count) {
+        return format!("{count} {singular}");
+    }
+
+    match plural {
+        Some(plural) => format!("{count} {plural}"),
+        None => format!("{count} {singular}s"),
+    }
+}
+
+/// Escapes characters with a special meaning in markdown, so that `value` is displayed verbatim.
+/// The result is marked as safe, so that it isn't escaped a second time in markdown templates.
+pub fn md_escape(value: &str) -> Value {
+    Value::from_safe_string(escape_markdown(value))
+}
+
+/// Quotes `value` line by line, e.g. the body of a comment. The lines are escaped, so that
+/// untrusted text can't inject links or code blocks into the message.
+pub fn blockquote(value: &str) -> Value {
+    Value::from_safe_string(
+        value
+            .lines()
+            .map(|line| format!("> {}", escape_markdown(line.trim())))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    )
+}
+
+/// Marks `value` as a URL, so that it can be used as a link target. See `escape_url`. Values that
+/// aren't http(s) or mailto URLs yield an empty target, so that e.g. `javascript:` URLs can't be
+/// followed. Use `link` for URLs from third parties, which shows them as plain text instead.
+pub fn url(value: &str) -> Value {
+    Value::from_safe_string(escape_url(value).unwrap_or_default())
+}
+
+/// Renders a link with the text `text` to the URL `value`, e.g.
+/// `alert.external_reference | link(alert.external_identifier)`. Values that aren't http(s) or
+/// mailto URLs are shown as plain text after `text` rather than being used as the link target.
+pub fn link(value: &str, text: &str) -> Value {
+    let text = escape_markdown(text);
+    Value::from_safe_string(match escape_url(value) {
+        Some(url) => format!("[{text}]({url})"),
+        None => format!("{text} ({})", escape_markdown(value)),
+    })
+}
+
+/// Wraps `value` in a fenced code block. The fence is longer than any run of backticks in `value`,
+/// so that the content can't close the code block early.
+pub fn code_block(value: &str, language: Option<&str>) -> Value {
+    let longest_backticks = value
+        .split(|c| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or_default();
+    let fence = "`".repeat(longest_backticks.max(2) + 1);
+
+    Value::from_safe_string(format!(
+        "{fence}{}\n{value}\n{fence}",
+        language.unwrap_or_default()
+    ))
+}
+
+/// Demotes all markdown headings in `value` by `levels` (defaults to 1), so that embedded markdown
+/// like release notes doesn't outshine the message around it. Headings in code blocks are left
+/// untouched.
+pub fn demote_headings(value: &str, levels: Option<usize>) -> String {
+    let levels = levels.unwrap_or(1);
+    let mut open_fence: Option<&str> = None;
+
+    value
+        .lines()
+        .map(|line| {
+            if let Some(fence) = code_fence(line) {
+                open_fence = toggle_fence(open_fence, fence);
+                return line.to_string();
+            }
+
+            if open_fence.is_some() {
+                return line.to_string();
+            }
+
+            let trimmed = line.trim_start();
+            let level = trimmed.chars().take_while(|c| *c == '#').count();
+            let is_heading = (1..=6).contains(&level)
+                && (trimmed.len() == level || trimmed[level..].starts_with([' ', '\t']));
+
+            if !is_heading {
+                return line.to_string();
+            }
+
+            format!(
+                "{}{}",
+                "#".repeat((level + levels).min(6)),
+                &trimmed[level..]
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Truncates the markdown in `value` to at most `length` characters. The text is cut at a line
+/// boundary, code blocks that would be left open are closed and an ellipsis is appended.
+pub fn truncate_markdown(value: &str, length: usize) -> String {
+    if value.chars().count() <= length {
+        return value.lines().collect::<Vec<_>>().join("\n");
+    }
+
+    let mut output = String::new();
+    let mut open_fence: Option<&str> = None;
+    let mut remaining = length;
+
+    for line in value.lines() {
+        let line_length = line.chars().count() + 1;
+
+        if line_length > remaining {
+            // Don't return an empty string just because the first line is too long.
+            if output.is_empty() {
+                output.extend(line.chars().take(remaining));
+                output.push('\n');
+            }
+            break;
+        }
+
+        if let Some(fence) = code_fence(line) {
+            open_fence = toggle_fence(open_fence, fence);
+        }
+
+        remaining -= line_length;
+        output.push_str(line);
+        output.push('\n');
+    }
+
+    if let Some(fence) = open_fence {
+        output.push_str(fence);
+        output.push('\n');
+    }
+
+    output.push('…');
+    output
+}
+
+/// Formats a number of bytes as a human-readable file size, e.g. `12.3 MB`.
+pub fn file_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["kB", "MB", "GB", "TB"];
+
+    if bytes < 1000 {
+        return format!("{bytes} B");
+    }
+
+    let mut size = bytes as f64 / 1000.0;
+    let mut unit = UNITS[0];
+
+    for next_unit in &UNITS[1..] {
+        if size < 1000.0 {
+            break;
+        }
+        size /= 1000.0;
+        unit = next_unit;
+    }
+
+    format!("{size:.1} {unit}")
+}
+
+fn parse_timestamp(value: &str) -> Result<DateTime<Utc>, Error> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .map_err(|err| {
+            Error::new(
+                ErrorKind::InvalidOperation,
+                format!("{value} is not a valid timestamp"),
+            )
+            .with_source(err)
+        })
+}
+
+fn format_relative_time(locale: Locale, seconds: i64) -> String {
+    let (amount, unit) = match (seconds.abs(), locale) {
+        (0..=59, Locale::En) => return "just now".to_string(),
+        (0..=59, Locale::Fr) => return "à l'instant".to_string(),
+        (seconds @ 60..=3599, _) => (seconds / 60, "minute"),
+        (seconds @ 3600..=86399, Locale::En) => (seconds / 3600, "hour"),
+        (seconds @ 3600..=86399, Locale::Fr) => (seconds / 3600, "heure"),
+        (seconds, Locale::En) => (seconds / 86400, "day"),
+        (seconds, Locale::Fr) => (seconds / 86400, "jour"),
+    };
+
+    let amount = pluralize_in(locale, amount, unit, None);
+
+    match (seconds < 0, locale) {
+        (true, Locale::En) => format!("in {amount}"),
+        (false, Locale::En) => format!("{amount} ago"),
+        (true, Locale::Fr) => format!("dans {amount}"),
+        (false, Locale::Fr) => format!("il y a {amount}"),
+    }
+}
+
+fn format_date(locale: Locale, timestamp: DateTime<Utc>) -> String {
+    let month = locale.month_name(timestamp.month());
+
+    match locale {
+        Locale::En => format!("{month} {}, {}", timestamp.day(), timestamp.year()),
+        Locale::Fr => format!("{} {month} {}", timestamp.day(), timestamp.year()),
+    }
+}
+
+fn format_duration(seconds: i64) -> String {
+    let (hours, minutes, seconds) = (seconds / 3600, seconds % 3600 / 60, seconds % 60);
+
+    let parts = [(hours, "h"), (minutes, "m"), (seconds, "s")]
+        .into_iter()
+        .filter(|(amount, _)| *amount != 0)
+        .map(|(amount, unit)| format!("{amount}{unit}"))
+        .collect::<Vec<_>>();
+
+    if parts.is_empty() {
+        return "0s".to_string();
+    }
+
+    parts.join(" ")
+}
+
+/// Returns the fence (e.g. "```") if `line` opens or closes a fenced code block.
+pub(crate) fn code_fence(line: &str) -> Option<&str> {
+    let trimmed = line.trim_start();
+    let fence_char = trimmed.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let fence_length = trimmed.chars().take_while(|c| *c == fence_char).count();
+    (fence_length >= 3).then(|| &trimmed[..fence_length])
+}
+
+/// Returns the fence that is open after encountering `fence` while `open_fence` was open.
+pub(crate) fn toggle_fence<'a>(open_fence: Option<&'a str>, fence: &'a str) -> Option<&'a str> {
+    match open_fence {
+        // A code block is closed by a fence of the same kind that is at least as long.
+        Some(open) if fence.starts_with(open) => None,
+        Some(open) => Some(open),
+        None => Some(fence),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_short_sha() {
+        assert_eq!(short_sha("9cc3bc379a1c8e1a2a99", None), "9cc3bc3");
+        assert_eq!(short_sha("9cc3bc379a1c8e1a2a99", Some(10)), "9cc3bc379a");
+    }
+
+    #[test]
+    fn test_branch_name() {
+        assert_eq!(branch_name("refs/heads/feature/vcard"), "feature/vcard");
+        assert_eq!(branch_name("refs/tags/0.1.0"), "0.1.0");
+        assert_eq!(branch_name("main"), "main");
+    }
+
+    #[test]
+    fn test_first_line() {
+        assert_eq!(
+            first_line("feat: Add vCard\r\n\nrefs #50"),
+            "feat: Add vCard"
+        );
+        assert_eq!(first_line(""), "");
+    }
+
+    #[test]
+    fn test_truncate_lines() {
+        assert_eq!(truncate_lines("a\nb", 2), "a\nb");
+        assert_eq!(truncate_lines("a\nb\nc", 2), "a\nb\n…");
+    }
+
+    #[test]
+    fn test_format_relative_time() {
+        assert_eq!(format_relative_time(Locale::En, 30), "just now");
+        assert_eq!(format_relative_time(Locale::En, 60), "1 minute ago");
+        assert_eq!(format_relative_time(Locale::En, 7300), "2 hours ago");
+        assert_eq!(format_relative_time(Locale::En, 3 * 86400), "3 days ago");
+        assert_eq!(format_relative_time(Locale::En, -120), "in 2 minutes");
+    }
+
+    #[test]
+    fn test_format_relative_time_in_french() {
+        assert_eq!(format_relative_time(Locale::Fr, 30), "à l'instant");
+        assert_eq!(format_relative_time(Locale::Fr, 7300), "il y a 2 heures");
+        assert_eq!(format_relative_time(Locale::Fr, -86400), "dans 1 jour");
+    }
+
+    #[test]
+    fn test_format_date() {
+        let timestamp = parse_timestamp("2024-06-27T20:44:49Z").unwrap();
+        assert_eq!(format_date(Locale::En, timestamp), "June 27, 2024");
+        assert_eq!(format_date(Locale::Fr, timestamp), "27 juin 2024");
+    }
+
+    #[test]
+    fn test_duration() {
+        assert_eq!(duration(Value::from(9), None).unwrap(), "9s");
+        assert_eq!(duration(Value::from(3720), None).unwrap(), "1h 2m");
+        assert_eq!(duration(Value::from(0), None).unwrap(), "0s");
+        assert_eq!(
+            duration(
+                Value::from("2024-06-27T20:44:49Z"),
+                Some("2024-06-27T20:46:01Z")
+            )
+            .unwrap(),
+            "1m 12s"
+        );
+        assert_eq!(
+            duration(Value::from("2024-06-27T20:44:49Z"), None).unwrap(),
+            "–"
+        );
+        assert_eq!(
+            duration(Value::UNDEFINED, Some("2024-06-27T20:46:01Z")).unwrap(),
+            "–"
+        );
+        assert_eq!(duration(Value::from(()), None).unwrap(), "–");
+    }
+
+    #[test]
+    fn test_author_name() {
+        let author = |username: &str, name: &str| {
+            Value::from_serialize(serde_json::json!({
+                "username": username,
+                "name": name,
+                "email": "mb@nesium.com",
+            }))
+        };
+        assert_eq!(author_name(author("nesium", "mb")).unwrap(), "nesium");
+        assert_eq!(author_name(author("", "mb")).unwrap(), "mb");
+        assert_eq!(author_name(author("", "")).unwrap(), "mb@nesium.com");
+        assert_eq!(author_name(Value::UNDEFINED).unwrap(), "unknown");
+    }
+
+    #[test]
+    fn test_emojis() {
+        assert_eq!(severity_emoji(Some("critical")), "🚨");
+        assert_eq!(severity_emoji(Some("Moderate")), "🟠");
+        assert_eq!(severity_emoji(Some("note")), "🟡");
+        assert_eq!(severity_emoji(None), "⚪");
+        assert_eq!(conclusion_emoji(Some("success")), "🟢");
+        assert_eq!(conclusion_emoji(Some("timed_out")), "🔴");
+    }
+
+    #[test]
+    fn test_limit() {
+        let items = Value::from(vec![1, 2, 3]);
+        assert_eq!(
+            limit(items.clone(), Some(2)).unwrap(),
+            Value::from(vec![1, 2])
+        );
+        assert_eq!(limit(items.clone(), Some(5)).unwrap(), items);
+        assert_eq!(limit(items.clone(), None).unwrap(), items);
+    }
+
+    #[test]
+    fn test_pluralize() {
+        assert_eq!(pluralize_in(Locale::En, 1, "commit", None), "1 commit");
+        assert_eq!(pluralize_in(Locale::En, 3, "commit", None), "3 commits");
+        assert_eq!(
+            pluralize_in(Locale::En, 0, "child", Some("children")),
+            "0 children"
+        );
+        assert_eq!(pluralize_in(Locale::Fr, 0, "commit", None), "0 commit");
+    }
+
+    #[test]
+    fn test_md_escape() {
+        let escaped = md_escape("Fix `foo` in [docs](x) *now*");
+        assert!(escaped.is_safe());
+        assert_eq!(
+            escaped.as_str(),
+            Some("Fix \\`foo\\` in \\[docs\\]\\(x\\) \\*now\\*")
+        );
+    }
+
+    #[test]
+    fn test_blockquote() {
+        assert_eq!(
+            blockquote("Use `x`.\r\n  [link](https://evil.com)\n```").as_str(),
+            Some("> Use \\`x\\`.\n> \\[link\\]\\(https://evil.com\\)\n> \\`\\`\\`")
+        );
+    }
+
+    #[test]
+    fn test_url() {
+        let escaped = url("https://github.com/Codertocat/Hello-World/issues?q=a_(b)");
+        assert!(escaped.is_safe());
+        assert_eq!(
+            escaped.as_str(),
+            Some("https://github.com/Codertocat/Hello-World/issues?q=a_%28b%29")
+        );
+        assert_eq!(url("javascript:alert(1)").as_str(), Some(""));
+    }
+
+    #[test]
+    fn test_link() {
+        let rendered = link("https://nvd.nist.gov/vuln/detail/CVE-2024-1", "CVE_2024_1");
+        assert!(rendered.is_safe());
+        assert_eq!(
+            rendered.as_str(),
+            Some("[CVE\\_2024\\_1](https://nvd.nist.gov/vuln/detail/CVE-2024-1)")
+        );
+        assert_eq!(
+            link("javascript:alert(1)", "CVE-2024-1").as_str(),
+            Some("CVE-2024-1 (javascript:alert\\(1\\))")
+        );
+    }
+
+    #[test]
+    fn test_code_block() {
+        assert_eq!(
+            code_block("let a = 1;", Some("rust")).as_str(),
+            Some("```rust\nlet a = 1;\n```")
+        );
+        assert_eq!(
+            code_block("```\ninjected\n```", None).as_str(),
+            Some("````\n```\ninjected\n```\n````")
+        );
+    }
+
+    #[test]
+    fn test_demote_headings() {
+        let markdown = "# Title\r\nSome text\n## Changes\n#hashtag\n```\n# comment\n```";
+        assert_eq!(
+            demote_headings(markdown, None),
+            "## Title\nSome text\n### Changes\n#hashtag\n```\n# comment\n```"
+        );
+        assert_eq!(demote_headings("##### Deep", Some(3)), "###### Deep");
+    }
+
+    #[test]
+    fn test_truncate_markdown_keeps_short_text() {
+        assert_eq!(truncate_markdown("- one\r\n- two", 20), "- one\n- two");
+    }
+
+    #[test]
+    fn test_truncate_markdown_cuts_at_line_boundary() {
+        assert_eq!(
+            truncate_markdown("- one\n- two\n- three", 13),
+            "- one\n- two\n…"
+        );
+    }
+
+    #[test]
+    fn test_truncate_markdown_closes_code_block() {
+        assert_eq!(
+            truncate_markdown("```rust\nlet a = 1;\nlet b = 2;\n```", 25),
+            "```rust\nlet a = 1;\n```\n…"
+        );
+    }
+
+    #[test]
+    fn test_truncate_markdown_cuts_long_first_line() {
+        assert_eq!(truncate_markdown("abcdefghij", 4), "abcd\n…");
+    }
+
+    #[test]
+    fn test_file_size() {
+        assert_eq!(file_size(512), "512 B");
+        assert_eq!(file_size(1_500), "1.5 kB");
+        assert_eq!(file_size(12_345_678), "12.3 MB");
+        assert_eq!(file_size(4_200_000_000), "4.2 GB");
+    }
+}
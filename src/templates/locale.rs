@@ -0,0 +1,99 @@
+use minijinja::State;
+
+/// The languages the locale-aware filters support. Templates themselves can be localized in any
+/// language, see `get_template_for_repo`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Locale {
+    #[default]
+    En,
+    Fr,
+}
+
+impl Locale {
+    /// Returns the locale of the message being rendered, i.e. the `locale` in the template's
+    /// context.
+    pub fn from_state(state: &State<'_, '_>) -> Self {
+        state
+            .lookup("locale")
+            .and_then(|locale| locale.as_str().map(Self::from_tag))
+            .unwrap_or_default()
+    }
+
+    /// Parses a language tag like "fr" or "fr-CA". Unsupported languages fall back to English.
+    pub fn from_tag(tag: &str) -> Self {
+        let language = tag.split(['-', '_']).next().unwrap_or_default();
+
+        if language.eq_ignore_ascii_case("fr") {
+            Self::Fr
+        } else {
+            Self::En
+        }
+    }
+
+    /// Returns `true` if `count` takes the singular form. French uses the singular for zero, too.
+    pub fn is_singular(self, count: i64) -> bool {
+        match self {
+            Self::En => count == 1,
+            Self::Fr => count == 0 || count == 1,
+        }
+    }
+
+    /// Returns the name of `month` (1-12).
+    pub fn month_name(self, month: u32) -> &'static str {
+        const EN: [&str; 12] = [
+            "January",
+            "February",
+            "March",
+            "April",
+            "May",
+            "June",
+            "July",
+            "August",
+            "September",
+            "October",
+            "November",
+            "December",
+        ];
+        const FR: [&str; 12] = [
+            "janvier",
+            "février",
+            "mars",
+            "avril",
+            "mai",
+            "juin",
+            "juillet",
+            "août",
+            "septembre",
+            "octobre",
+            "novembre",
+            "décembre",
+        ];
+
+        let names = match self {
+            Self::En => &EN,
+            Self::Fr => &FR,
+        };
+        names[(month as usize).clamp(1, 12) - 1]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_tag() {
+        assert_eq!(Locale::from_tag("fr"), Locale::Fr);
+        assert_eq!(Locale::from_tag("fr-CA"), Locale::Fr);
+        assert_eq!(Locale::from_tag("en_GB"), Locale::En);
+        assert_eq!(Locale::from_tag("de"), Locale::En);
+    }
+
+    #[test]
+    fn test_is_singular() {
+        assert!(!Locale::En.is_singular(0));
+        assert!(Locale::Fr.is_singular(0));
+        assert!(Locale::Fr.is_singular(1));
+        assert!(!Locale::Fr.is_singular(2));
+    }
+}
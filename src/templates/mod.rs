@@ -0,0 +1,544 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use anyhow::Result;
+use include_dir::{include_dir, Dir};
+use minijinja::value::Value;
+use minijinja::{context, Environment, Template};
+use tracing::{error, info, warn};
+
+mod escape;
+pub(crate) mod filters;
+mod locale;
+
+/// The templates shipped with the binary. Templates on disk take precedence over these.
+static DEFAULT_TEMPLATES: Dir<'_> = include_dir!("$CARGO_MANIFEST_DIR/templates");
+
+/// The values of `max_items` that are tried in turn when a message exceeds the maximum size.
+const MAX_ITEMS_STEPS: [usize; 4] = [20, 10, 5, 1];
+
+/// The templates used to render messages. They can be reloaded from disk at runtime without
+/// affecting renders that are in progress.
+#[derive(Clone)]
+pub struct Templates {
+    directory: Option<PathBuf>,
+    max_message_size: usize,
+    current: Arc<RwLock<LoadedTemplates>>,
+}
+
+struct LoadedTemplates {
+    environment: Arc<Environment<'static>>,
+    sources: BTreeMap<String, String>,
+}
+
+impl Templates {
+    /// Loads the built-in templates and overrides them with the templates found in `directory`,
+    /// if any.
+    pub fn load(directory: Option<PathBuf>) -> Result<Self> {
+        if let Some(directory) = directory.as_ref().filter(|directory| !directory.is_dir()) {
+            warn!(
+                "Templates directory {} does not exist. Using built-in templates.",
+                directory.display()
+            );
+        }
+
+        let sources = read_sources(directory.as_deref())?;
+        let environment = build_environment(&sources)?;
+
+        Ok(Self {
+            directory,
+            max_message_size: 0,
+            current: Arc::new(RwLock::new(LoadedTemplates {
+                environment: Arc::new(environment),
+                sources,
+            })),
+        })
+    }
+
+    /// Sets the maximum size of a rendered message in bytes, see `render`. `0` disables the limit.
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    /// Renders `template` with `ctx`. If the message exceeds the maximum message size, the
+    /// template is rendered again with a decreasing `max_items` in its context, which templates
+    /// can use to shorten lists (e.g. `event.commits | limit(max_items)`). Returns the shortest
+    /// message if it never fits, it's up to the XMPP service to split it then.
+    pub fn render(
+        &self,
+        template: &Template<'_, '_>,
+        ctx: Value,
+    ) -> Result<String, minijinja::Error> {
+        let mut message = template.render(&ctx)?;
+
+        for max_items in MAX_ITEMS_STEPS {
+            if self.max_message_size == 0 || message.len() <= self.max_message_size {
+                break;
+            }
+            message = template.render(context!(max_items => max_items, ..ctx.clone()))?;
+        }
+
+        Ok(message)
+    }
+
+    /// Returns the current environment. Hold on to it for the duration of a render so that a
+    /// concurrent reload doesn't mix templates of different versions.
+    pub fn environment(&self) -> Arc<Environment<'static>> {
+        self.current.read().unwrap().environment.clone()
+    }
+
+    /// Reloads all templates from disk and atomically replaces the current environment. Returns
+    /// the names of the templates that were added, removed or changed. If any template fails to
+    /// compile, the current environment is kept and an error is returned.
+    pub fn reload(&self) -> Result<Vec<String>> {
+        let sources = read_sources(self.directory.as_deref())?;
+        let environment = build_environment(&sources)?;
+
+        let mut current = self.current.write().unwrap();
+        let changed_templates = changed_templates(&current.sources, &sources);
+        *current = LoadedTemplates {
+            environment: Arc::new(environment),
+            sources,
+        };
+
+        Ok(changed_templates)
+    }
+}
+
+/// Reloads `templates` whenever the process receives SIGHUP.
+#[cfg(unix)]
+pub async fn reload_on_sighup(templates: Templates) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(err) => {
+            error!("Failed to listen for SIGHUP. Templates can't be reloaded. {err}");
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        match templates.reload() {
+            Ok(changed_templates) if changed_templates.is_empty() => {
+                info!("Reloaded templates. No templates changed.")
+            }
+            Ok(changed_templates) => info!(
+                "Reloaded templates. Changed templates: {}",
+                changed_templates.join(", ")
+            ),
+            Err(err) => error!("Failed to reload templates. Keeping previous templates. {err:#}"),
+        }
+    }
+}
+
+/// Returns the template `name` for `repo` (e.g. "owner/repo") in `locale` (e.g. "fr").
+/// Repository- and owner-specific templates take precedence, i.e. the lookup order is
+/// `owner/repo/name` → `owner/name` → `name`. At each of these levels a localized template like
+/// `push.fr.md` is preferred over `push.md`.
+pub fn get_template_for_repo<'env>(
+    environment: &'env Environment<'static>,
+    repo: &str,
+    name: &str,
+    locale: Option<&str>,
+) -> Option<Template<'env, 'env>> {
+    let localized_name = locale.and_then(|locale| localized_name(name, locale));
+
+    lookup_prefixes(repo)
+        .into_iter()
+        .flat_map(|prefix| {
+            [localized_name.as_deref(), Some(name)]
+                .into_iter()
+                .flatten()
+                .map(move |name| format!("{prefix}{name}"))
+        })
+        .find_map(|name| environment.get_template(&name).ok())
+}
+
+/// Returns the prefixes of the templates names for `repo` in lookup order, i.e. `owner/repo/`,
+/// `owner/` and the empty prefix of the default templates.
+fn lookup_prefixes(repo: &str) -> Vec<String> {
+    let owner = repo.split_once('/').map(|(owner, _)| owner);
+    [Some(repo), owner]
+        .into_iter()
+        .flatten()
+        .map(|prefix| format!("{prefix}/"))
+        .chain([String::new()])
+        .collect()
+}
+
+/// Returns the name of the `locale` variant of the template `name`, e.g. `push.fr.md` for
+/// `push.md`.
+fn localized_name(name: &str, locale: &str) -> Option<String> {
+    let (stem, extension) = name.rsplit_once('.')?;
+    Some(format!("{stem}.{locale}.{extension}"))
+}
+
+/// Splits a template name like `push.fr.md` into `push.md` and its locale.
+pub fn split_locale(name: &str) -> (String, Option<&str>) {
+    match name.split('.').collect::<Vec<_>>()[..] {
+        [stem, locale, extension] => (format!("{stem}.{extension}"), Some(locale)),
+        _ => (name.to_string(), None),
+    }
+}
+
+/// Returns `true` if `environment` contains at least one template for events of `event_type` that
+/// `get_template_for_repo` finds for `repo` in `locale`, i.e. including repository- and
+/// owner-specific as well as localized templates.
+pub fn supports_event(
+    environment: &Environment<'_>,
+    repo: &str,
+    event_type: &str,
+    locale: Option<&str>,
+) -> bool {
+    let event_template = format!("{event_type}.md");
+    let action_template_prefix = format!("{event_type}__");
+    let prefixes = lookup_prefixes(repo);
+
+    environment.templates().any(|(name, _)| {
+        prefixes.iter().any(|prefix| {
+            let Some(name) = name
+                .strip_prefix(prefix.as_str())
+                .filter(|name| !name.contains('/'))
+            else {
+                return false;
+            };
+            let (name, template_locale) = split_locale(name);
+            (name == event_template || name.starts_with(&action_template_prefix))
+                && (template_locale.is_none() || template_locale == locale)
+        })
+    })
+}
+
+fn read_sources(directory: Option<&Path>) -> Result<BTreeMap<String, String>> {
+    let mut sources = BTreeMap::new();
+    read_default_sources_into(&DEFAULT_TEMPLATES, &mut sources);
+
+    if let Some(directory) = directory.filter(|directory| directory.is_dir()) {
+        read_sources_into(directory, "", &mut sources)?;
+    }
+
+    Ok(sources)
+}
+
+fn read_default_sources_into(directory: &Dir<'_>, sources: &mut BTreeMap<String, String>) {
+    for file in directory.files() {
+        if file.path().extension() != Some("j2".as_ref()) {
+            continue;
+        }
+
+        let template_name = file.path().with_extension("");
+        let (Some(template_name), Some(template)) = (template_name.to_str(), file.contents_utf8())
+        else {
+            continue;
+        };
+
+        sources.insert(template_name.replace('\\', "/"), template.to_string());
+    }
+
+    for directory in directory.dirs() {
+        read_default_sources_into(directory, sources);
+    }
+}
+
+/// Reads all templates in `directory` and its subdirectories. Templates in subdirectories are
+/// named after their path relative to the templates directory, e.g. `owner/repo/push.md`.
+fn read_sources_into(
+    directory: &Path,
+    prefix: &str,
+    sources: &mut BTreeMap<String, String>,
+) -> Result<()> {
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let path = entry.path();
+
+        if path.is_dir() {
+            let Some(directory_name) = path.file_name().and_then(|file| file.to_str()) else {
+                continue;
+            };
+            read_sources_into(&path, &format!("{prefix}{directory_name}/"), sources)?;
+            continue;
+        }
+
+        if !path.is_file() || path.extension() != Some("j2".as_ref()) {
+            continue;
+        }
+
+        let Some(template_name) = path
+            .with_extension("")
+            .file_name()
+            .and_then(|file| file.to_str())
+            .map(|s| format!("{prefix}{s}"))
+        else {
+            continue;
+        };
+
+        sources.insert(template_name, fs::read_to_string(&path)?);
+    }
+
+    Ok(())
+}
+
+fn build_environment(sources: &BTreeMap<String, String>) -> Result<Environment<'static>> {
+    let mut env = Environment::new();
+    env.set_auto_escape_callback(escape::auto_escape_callback);
+    env.set_formatter(escape::format);
+    env.add_filter("short_sha", filters::short_sha);
+    env.add_filter("branch_name", filters::branch_name);
+    env.add_filter("first_line", filters::first_line);
+    env.add_filter("truncate_lines", filters::truncate_lines);
+    env.add_filter("limit", filters::limit);
+    env.add_filter("relative_time", filters::relative_time);
+    env.add_filter("date", filters::date);
+    env.add_filter("duration", filters::duration);
+    env.add_filter("pluralize", filters::pluralize);
+    env.add_filter("md_escape", filters::md_escape);
+    env.add_filter("author_name", filters::author_name);
+    env.add_filter("severity_emoji", filters::severity_emoji);
+    env.add_filter("conclusion_emoji", filters::conclusion_emoji);
+    env.add_filter("url", filters::url);
+    env.add_filter("link", filters::link);
+    env.add_filter("blockquote", filters::blockquote);
+    env.add_filter("code_block", filters::code_block);
+    env.add_filter("demote_headings", filters::demote_headings);
+    env.add_filter("truncate_markdown", filters::truncate_markdown);
+    env.add_filter("file_size", filters::file_size);
+
+    for (name, source) in sources {
+        env.add_template_owned(name.clone(), source.clone())?;
+    }
+
+    Ok(env)
+}
+
+fn changed_templates(
+    old: &BTreeMap<String, String>,
+    new: &BTreeMap<String, String>,
+) -> Vec<String> {
+    let mut names = old
+        .keys()
+        .chain(new.keys())
+        .filter(|name| old.get(*name) != new.get(*name))
+        .cloned()
+        .collect::<Vec<_>>();
+    names.sort();
+    names.dedup();
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn write(dir: &TempDir, file: &str, contents: &str) {
+        let path = dir.path().join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    fn render(templates: &Templates, name: &str) -> String {
+        templates
+            .environment()
+            .get_template(name)
+            .unwrap()
+            .render(())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_reload_reports_changed_templates() {
+        let dir = TempDir::new().unwrap();
+        write(&dir, "push.md.j2", "push");
+        write(&dir, "issues__opened.md.j2", "opened");
+        write(&dir, "issues__closed.md.j2", "closed");
+
+        let templates = Templates::load(Some(dir.path().to_path_buf())).unwrap();
+
+        write(&dir, "push.md.j2", "new push");
+        fs::remove_file(dir.path().join("issues__closed.md.j2")).unwrap();
+        write(&dir, "release__released.md.j2", "released");
+
+        assert_eq!(
+            templates.reload().unwrap(),
+            vec!["issues__closed.md", "push.md", "release__released.md"]
+        );
+        assert_eq!(render(&templates, "push.md"), "new push");
+    }
+
+    #[test]
+    fn test_get_template_for_repo_prefers_overrides() {
+        let dir = TempDir::new().unwrap();
+        write(&dir, "push.md.j2", "default");
+        write(&dir, "issues__opened.md.j2", "default");
+        write(&dir, "prose-im/push.md.j2", "owner");
+        write(&dir, "prose-im/issues__opened.md.j2", "owner");
+        write(&dir, "prose-im/prose-core-client/push.md.j2", "repo");
+
+        let templates = Templates::load(Some(dir.path().to_path_buf())).unwrap();
+        let environment = templates.environment();
+        let render = |repo: &str, name: &str| {
+            get_template_for_repo(&environment, repo, name, None)
+                .map(|template| template.render(()).unwrap())
+        };
+
+        assert_eq!(
+            render("prose-im/prose-core-client", "push.md").as_deref(),
+            Some("repo")
+        );
+        assert_eq!(
+            render("prose-im/prose-core-client", "issues__opened.md").as_deref(),
+            Some("owner")
+        );
+        assert_eq!(
+            render("prose-im/prose-web", "push.md").as_deref(),
+            Some("owner")
+        );
+        assert_eq!(
+            render("nesium/test_repo", "push.md").as_deref(),
+            Some("default")
+        );
+        assert_eq!(render("nesium/test_repo", "unknown__event.md"), None);
+    }
+
+    #[test]
+    fn test_get_template_for_repo_prefers_localized_templates() {
+        let dir = TempDir::new().unwrap();
+        write(&dir, "push.md.j2", "default");
+        write(&dir, "push.fr.md.j2", "french");
+        write(&dir, "prose-im/push.md.j2", "owner");
+        write(
+            &dir,
+            "prose-im/prose-core-client/push.fr.md.j2",
+            "repo french",
+        );
+
+        let templates = Templates::load(Some(dir.path().to_path_buf())).unwrap();
+        let environment = templates.environment();
+        let render = |repo: &str, locale: Option<&str>| {
+            get_template_for_repo(&environment, repo, "push.md", locale)
+                .map(|template| template.render(()).unwrap())
+        };
+
+        assert_eq!(
+            render("prose-im/prose-core-client", Some("fr")).as_deref(),
+            Some("repo french")
+        );
+        assert_eq!(
+            render("prose-im/prose-web", Some("fr")).as_deref(),
+            Some("owner")
+        );
+        assert_eq!(
+            render("nesium/test_repo", Some("fr")).as_deref(),
+            Some("french")
+        );
+        assert_eq!(
+            render("nesium/test_repo", Some("de")).as_deref(),
+            Some("default")
+        );
+        assert_eq!(render("nesium/test_repo", None).as_deref(), Some("default"));
+    }
+
+    #[test]
+    fn test_supports_event_finds_overrides_and_localized_templates() {
+        let dir = TempDir::new().unwrap();
+        write(&dir, "prose-im/deployment.md.j2", "owner");
+        write(&dir, "deployment_status__created.fr.md.j2", "french");
+
+        let templates = Templates::load(Some(dir.path().to_path_buf())).unwrap();
+        let environment = templates.environment();
+
+        assert!(supports_event(
+            &environment,
+            "prose-im/prose-web",
+            "deployment",
+            None
+        ));
+        assert!(!supports_event(
+            &environment,
+            "nesium/test_repo",
+            "deployment",
+            None
+        ));
+        assert!(supports_event(
+            &environment,
+            "nesium/test_repo",
+            "deployment_status",
+            Some("fr")
+        ));
+        assert!(!supports_event(
+            &environment,
+            "nesium/test_repo",
+            "deployment_status",
+            None
+        ));
+        assert!(supports_event(
+            &environment,
+            "nesium/test_repo",
+            "push",
+            None
+        ));
+    }
+
+    #[test]
+    fn test_templates_on_disk_override_built_in_templates() {
+        let dir = TempDir::new().unwrap();
+        write(&dir, "push.md.j2", "custom push");
+
+        let templates = Templates::load(Some(dir.path().to_path_buf())).unwrap();
+
+        assert_eq!(render(&templates, "push.md"), "custom push");
+        assert!(templates
+            .environment()
+            .get_template("issues__opened.md")
+            .is_ok());
+    }
+
+    #[test]
+    fn test_missing_directory_uses_built_in_templates() {
+        let templates = Templates::load(Some("/does/not/exist".into())).unwrap();
+        assert!(templates.environment().get_template("push.md").is_ok());
+    }
+
+    #[test]
+    fn test_render_limits_items_to_fit_max_message_size() {
+        let dir = TempDir::new().unwrap();
+        write(
+            &dir,
+            "push.md.j2",
+            "{% for item in items | limit(max_items) %}{{ item }} {% endfor %}",
+        );
+
+        let templates = Templates::load(Some(dir.path().to_path_buf()))
+            .unwrap()
+            .with_max_message_size(30);
+        let environment = templates.environment();
+        let template = environment.get_template("push.md").unwrap();
+        let render = |count: usize| {
+            templates
+                .render(&template, context!(items => (0..count).collect::<Vec<_>>()))
+                .unwrap()
+        };
+
+        assert_eq!(render(3), "0 1 2 ");
+        assert_eq!(render(30), "0 1 2 3 4 5 6 7 8 9 ");
+    }
+
+    #[test]
+    fn test_reload_keeps_previous_templates_on_error() {
+        let dir = TempDir::new().unwrap();
+        write(&dir, "push.md.j2", "push");
+
+        let templates = Templates::load(Some(dir.path().to_path_buf())).unwrap();
+
+        write(&dir, "push.md.j2", "new push");
+        write(&dir, "issues__opened.md.j2", "{% if %}");
+
+        assert!(templates.reload().is_err());
+        assert_eq!(render(&templates, "push.md"), "push");
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde_json::Value;
+
+/// A store that collects label changes on issues, so that rapid successive changes can be
+/// reported in a single message.
+#[derive(Debug, Clone)]
+pub struct LabelChangesStore {
+    delay: Duration,
+    pending: Arc<Mutex<HashMap<IssueKey, LabelChanges>>>,
+}
+
+/// The net label changes of an issue, along with the most recent event that changed them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LabelChanges {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub event: HashMap<String, Value>,
+}
+
+impl LabelChangesStore {
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            pending: Default::default(),
+        }
+    }
+
+    /// Returns the time to wait for further changes before reporting them or `None` if label
+    /// changes should not be collapsed.
+    pub fn delay(&self) -> Option<Duration> {
+        (!self.delay.is_zero()).then_some(self.delay)
+    }
+
+    /// Records that `label` was added to an issue. Returns `true` if this is the first pending
+    /// change for the issue, in which case the caller is responsible for calling `take` after
+    /// `delay`.
+    ///
+    /// # Arguments
+    /// * `repo` - The repository name.
+    /// * `issue_number` - The number of the issue within the repository.
+    /// * `label` - The name of the added label.
+    /// * `event` - The event that added the label.
+    pub fn label_added(
+        &self,
+        repo: impl Into<String>,
+        issue_number: u64,
+        label: impl Into<String>,
+        event: HashMap<String, Value>,
+    ) -> bool {
+        self.record(repo.into(), issue_number, event, |changes| {
+            apply_change(&mut changes.added, &mut changes.removed, label.into())
+        })
+    }
+
+    /// Records that `label` was removed from an issue. Returns `true` if this is the first pending
+    /// change for the issue, in which case the caller is responsible for calling `take` after
+    /// `delay`.
+    ///
+    /// # Arguments
+    /// * `repo` - The repository name.
+    /// * `issue_number` - The number of the issue within the repository.
+    /// * `label` - The name of the removed label.
+    /// * `event` - The event that removed the label.
+    pub fn label_removed(
+        &self,
+        repo: impl Into<String>,
+        issue_number: u64,
+        label: impl Into<String>,
+        event: HashMap<String, Value>,
+    ) -> bool {
+        self.record(repo.into(), issue_number, event, |changes| {
+            apply_change(&mut changes.removed, &mut changes.added, label.into())
+        })
+    }
+
+    /// Removes and returns the pending changes of an issue. Returns `None` if there were no
+    /// pending changes or if they cancelled each other out.
+    pub fn take(&self, repo: impl Into<String>, issue_number: u64) -> Option<LabelChanges> {
+        let key = IssueKey {
+            repo: repo.into(),
+            issue_number,
+        };
+        let changes = self.pending.lock().unwrap().remove(&key)?;
+
+        if changes.added.is_empty() && changes.removed.is_empty() {
+            return None;
+        }
+
+        Some(changes)
+    }
+
+    fn record(
+        &self,
+        repo: String,
+        issue_number: u64,
+        event: HashMap<String, Value>,
+        apply: impl FnOnce(&mut LabelChanges),
+    ) -> bool {
+        let mut map = self.pending.lock().unwrap();
+        let key = IssueKey { repo, issue_number };
+        let is_first_change = !map.contains_key(&key);
+
+        let changes = map.entry(key).or_insert_with(|| LabelChanges {
+            added: vec![],
+            removed: vec![],
+            event: Default::default(),
+        });
+        apply(changes);
+        changes.event = event;
+
+        is_first_change
+    }
+}
+
+/// Adds `label` to `target` unless it is contained in `opposite`, in which case the two changes
+/// cancel each other out.
+fn apply_change(target: &mut Vec<String>, opposite: &mut Vec<String>, label: String) {
+    if let Some(idx) = opposite.iter().position(|l| l == &label) {
+        opposite.remove(idx);
+        return;
+    }
+
+    if !target.contains(&label) {
+        target.push(label);
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct IssueKey {
+    repo: String,
+    issue_number: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay_is_none_when_zero() {
+        assert_eq!(LabelChangesStore::new(Duration::ZERO).delay(), None);
+        assert_eq!(
+            LabelChangesStore::new(Duration::from_secs(5)).delay(),
+            Some(Duration::from_secs(5))
+        );
+    }
+
+    #[test]
+    fn test_only_first_change_is_reported_as_first() {
+        let store = LabelChangesStore::new(Duration::from_secs(5));
+        assert!(store.label_added("repo1", 1, "bug", Default::default()));
+        assert!(!store.label_added("repo1", 1, "enhancement", Default::default()));
+        assert!(store.label_added("repo1", 2, "bug", Default::default()));
+    }
+
+    #[test]
+    fn test_take_returns_collapsed_changes() {
+        let store = LabelChangesStore::new(Duration::from_secs(5));
+        store.label_added("repo1", 1, "enhancement", Default::default());
+        store.label_added("repo1", 1, "enhancement", Default::default());
+        store.label_removed("repo1", 1, "bug", Default::default());
+
+        let changes = store.take("repo1", 1).unwrap();
+        assert_eq!(changes.added, vec!["enhancement".to_string()]);
+        assert_eq!(changes.removed, vec!["bug".to_string()]);
+        assert!(store.take("repo1", 1).is_none());
+    }
+
+    #[test]
+    fn test_opposite_changes_cancel_out() {
+        let store = LabelChangesStore::new(Duration::from_secs(5));
+        store.label_added("repo1", 1, "bug", Default::default());
+        store.label_removed("repo1", 1, "bug", Default::default());
+        assert!(store.take("repo1", 1).is_none());
+    }
+
+    #[test]
+    fn test_take_keeps_latest_event() {
+        let store = LabelChangesStore::new(Duration::from_secs(5));
+        let latest = HashMap::from([("action".to_string(), Value::from("unlabeled"))]);
+        store.label_added("repo1", 1, "bug", Default::default());
+        store.label_removed("repo1", 1, "wontfix", latest.clone());
+        assert_eq!(store.take("repo1", 1).unwrap().event, latest);
+    }
+}
@@ -1,5 +1,14 @@
-pub use repo_mapping::RepoMapping;
-pub use workflow_runs_store::WorkflowRunsStore;
+pub use label_changes_store::{LabelChanges, LabelChangesStore};
+pub use mutes_store::{post_expired_mutes, Mute, MuteTarget, MutesStore};
+pub use repo_mapping::{sync_rooms, RepoMapping, SubscriptionError};
+pub use subscriptions_store::SubscriptionsStore;
+pub use threads_store::ThreadsStore;
+pub use workflow_runs_store::{Report, WorkflowRunsStore};
 
+mod label_changes_store;
+mod mutes_store;
+mod persistence;
 mod repo_mapping;
+mod subscriptions_store;
+mod threads_store;
 mod workflow_runs_store;
@@ -0,0 +1,335 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{bail, format_err, Result};
+use chrono::{DateTime, Utc};
+use prose_xmpp::BareJid;
+use serde::{Deserialize, Serialize};
+
+use crate::services::xmpp_service::{OutgoingMessage, RoomId};
+use crate::services::XMPPService;
+use crate::webhook::{persistence, RepoMapping};
+
+/// How often expired mutes are looked for.
+const EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// How often the counts of suppressed events are persisted.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+/// The longest a mute can last.
+pub const MAX_MUTE_DURATION: Duration = Duration::from_secs(90 * 24 * 60 * 60);
+
+/// A store for the mutes that suppress notifications for a while, e.g. during an incident. Mutes
+/// are persisted to a file so that they survive restarts.
+#[derive(Debug, Clone)]
+pub struct MutesStore {
+    path: Option<PathBuf>,
+    mutes: Arc<Mutex<Vec<Mute>>>,
+    /// Whether events were suppressed since the mutes were last persisted.
+    is_dirty: Arc<AtomicBool>,
+}
+
+/// Suppresses events sent to a room or about a repository until `until`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Mute {
+    pub target: MuteTarget,
+    /// The muted event types, e.g. "push". An empty list mutes all events.
+    pub event_types: Vec<String>,
+    pub until: DateTime<Utc>,
+    /// The number of suppressed events by event type.
+    #[serde(default)]
+    pub suppressed: BTreeMap<String, u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MuteTarget {
+    Room(BareJid),
+    /// A repository, e.g. "owner/name".
+    Repo(String),
+}
+
+impl MutesStore {
+    /// Loads the mutes persisted at `path`. Mutes are kept in memory only if `path` is `None`.
+    pub fn load(path: Option<PathBuf>) -> Result<Self> {
+        let mutes = persistence::load(path.as_deref())?;
+
+        Ok(Self {
+            path,
+            mutes: Arc::new(Mutex::new(mutes)),
+            is_dirty: Default::default(),
+        })
+    }
+
+    /// Mutes `event_types` (or all events if empty) of `target` for `duration`, which is at most
+    /// `MAX_MUTE_DURATION`. Replaces an existing mute of `target` but keeps its count of
+    /// suppressed events.
+    pub fn mute(
+        &self,
+        target: MuteTarget,
+        event_types: Vec<String>,
+        duration: Duration,
+    ) -> Result<Mute> {
+        if duration > MAX_MUTE_DURATION {
+            bail!(
+                "Mutes can last at most {} days.",
+                MAX_MUTE_DURATION.as_secs() / (24 * 60 * 60)
+            );
+        }
+        let until = Utc::now()
+            .checked_add_signed(chrono::Duration::from_std(duration)?)
+            .ok_or_else(|| format_err!("The mute would last too long."))?;
+        let mut mutes = self.mutes.lock().unwrap();
+
+        let suppressed = match mutes.iter().position(|mute| mute.target == target) {
+            Some(idx) => mutes.remove(idx).suppressed,
+            None => Default::default(),
+        };
+
+        let mute = Mute {
+            target,
+            event_types,
+            until,
+            suppressed,
+        };
+        mutes.push(mute.clone());
+        self.save(&mutes);
+
+        Ok(mute)
+    }
+
+    /// Removes and returns the mute of `target`, if any.
+    pub fn unmute(&self, target: &MuteTarget) -> Option<Mute> {
+        let mut mutes = self.mutes.lock().unwrap();
+        let idx = mutes.iter().position(|mute| &mute.target == target)?;
+        let mute = mutes.remove(idx);
+        self.save(&mutes);
+        Some(mute)
+    }
+
+    pub fn list(&self) -> Vec<Mute> {
+        self.mutes.lock().unwrap().clone()
+    }
+
+    /// Returns `true` if an event of `event_type` about `repo` that would be sent to `room` is
+    /// muted, in which case it is counted as suppressed. The counts are persisted by `flush`,
+    /// rather than on every event during a flood.
+    pub fn suppress(&self, room: &BareJid, repo: &str, event_type: &str) -> bool {
+        let now = Utc::now();
+        let mut mutes = self.mutes.lock().unwrap();
+
+        let Some(mute) = mutes.iter_mut().find(|mute| {
+            mute.until > now
+                && mute.matches_event_type(event_type)
+                && match &mute.target {
+                    MuteTarget::Room(muted_room) => muted_room == room,
+                    MuteTarget::Repo(muted_repo) => muted_repo == repo,
+                }
+        }) else {
+            return false;
+        };
+
+        *mute.suppressed.entry(event_type.to_string()).or_default() += 1;
+        self.is_dirty.store(true, Ordering::Release);
+        true
+    }
+
+    /// Persists the mutes if events were suppressed since they were last persisted.
+    pub fn flush(&self) {
+        if !self.is_dirty.load(Ordering::Acquire) {
+            return;
+        }
+        let mutes = self.mutes.lock().unwrap();
+        self.save(&mutes);
+    }
+
+    /// Removes and returns the mutes that have expired.
+    pub fn take_expired(&self) -> Vec<Mute> {
+        let now = Utc::now();
+        let mut mutes = self.mutes.lock().unwrap();
+
+        let (expired, active): (Vec<_>, Vec<_>) =
+            mutes.drain(..).partition(|mute| mute.until <= now);
+        *mutes = active;
+
+        if !expired.is_empty() {
+            self.save(&mutes);
+        }
+
+        expired
+    }
+
+    fn save(&self, mutes: &[Mute]) {
+        self.is_dirty.store(false, Ordering::Release);
+        persistence::save(self.path.as_deref(), mutes);
+    }
+}
+
+impl Mute {
+    fn matches_event_type(&self, event_type: &str) -> bool {
+        self.event_types.is_empty() || self.event_types.iter().any(|muted| muted == event_type)
+    }
+
+    /// The room notifications about the target of the mute go to.
+    pub fn room(&self, mapping: &RepoMapping) -> Option<BareJid> {
+        match &self.target {
+            MuteTarget::Room(room) => Some(room.clone()),
+            MuteTarget::Repo(repo) => mapping.get(repo).map(|settings| settings.room),
+        }
+    }
+
+    /// A message summarizing the events that were suppressed while muted.
+    pub fn summary(&self) -> String {
+        let target = match &self.target {
+            MuteTarget::Room(_) => "this room".to_string(),
+            MuteTarget::Repo(repo) => format!("`{repo}`"),
+        };
+
+        if self.suppressed.is_empty() {
+            return format!("Unmuted {target}. No events were suppressed.");
+        }
+
+        let suppressed = self
+            .suppressed
+            .iter()
+            .map(|(event_type, count)| format!("{count} × `{event_type}`"))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        format!("Unmuted {target}. Suppressed while muted: {suppressed}.")
+    }
+}
+
+/// Periodically removes expired mutes and posts a summary of the events they suppressed. Persists
+/// the counts of suppressed events in between.
+pub async fn post_expired_mutes(
+    mutes: MutesStore,
+    mapping: RepoMapping,
+    xmpp: Arc<dyn XMPPService>,
+) {
+    let mut interval = tokio::time::interval(EXPIRY_CHECK_INTERVAL);
+    let mut flush_interval = tokio::time::interval(FLUSH_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => (),
+            _ = flush_interval.tick() => {
+                mutes.flush();
+                continue;
+            }
+        }
+
+        for mute in mutes.take_expired() {
+            if let Some(room) = mute.room(&mapping) {
+                xmpp.send_message(OutgoingMessage::new(RoomId::Room(room), mute.summary()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    fn room() -> BareJid {
+        "room@example.org".parse().unwrap()
+    }
+
+    #[test]
+    fn test_mute_suppresses_selected_event_types() {
+        let store = MutesStore::load(None).unwrap();
+        store
+            .mute(MuteTarget::Room(room()), vec!["push".to_string()], HOUR)
+            .unwrap();
+
+        assert!(store.suppress(&room(), "owner/repo", "push"));
+        assert!(store.suppress(&room(), "owner/repo", "push"));
+        assert!(!store.suppress(&room(), "owner/repo", "issues"));
+        assert!(!store.suppress(&"other@example.org".parse().unwrap(), "owner/repo", "push"));
+
+        let mute = store.unmute(&MuteTarget::Room(room())).unwrap();
+        assert_eq!(mute.suppressed, BTreeMap::from([("push".to_string(), 2)]));
+        assert!(!store.suppress(&room(), "owner/repo", "push"));
+    }
+
+    #[test]
+    fn test_repo_mute_suppresses_all_events() {
+        let store = MutesStore::load(None).unwrap();
+        store
+            .mute(MuteTarget::Repo("owner/repo".to_string()), vec![], HOUR)
+            .unwrap();
+
+        assert!(store.suppress(&room(), "owner/repo", "issues"));
+        assert!(!store.suppress(&room(), "owner/other", "issues"));
+    }
+
+    #[test]
+    fn test_mute_duration_is_capped() {
+        let store = MutesStore::load(None).unwrap();
+
+        assert!(store
+            .mute(MuteTarget::Room(room()), vec![], MAX_MUTE_DURATION)
+            .is_ok());
+        assert!(store
+            .mute(
+                MuteTarget::Room(room()),
+                vec![],
+                Duration::from_secs(100_000_000 * 24 * 60 * 60)
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_take_expired_removes_expired_mutes() {
+        let store = MutesStore::load(None).unwrap();
+        store
+            .mute(MuteTarget::Room(room()), vec![], Duration::ZERO)
+            .unwrap();
+        store
+            .mute(MuteTarget::Repo("owner/repo".to_string()), vec![], HOUR)
+            .unwrap();
+
+        assert!(!store.suppress(&room(), "owner/other", "push"));
+
+        let expired = store.take_expired();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].target, MuteTarget::Room(room()));
+        assert_eq!(store.list().len(), 1);
+    }
+
+    #[test]
+    fn test_mutes_are_persisted() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mutes.json");
+
+        let store = MutesStore::load(Some(path.clone())).unwrap();
+        store.mute(MuteTarget::Room(room()), vec![], HOUR).unwrap();
+        store.suppress(&room(), "owner/repo", "push");
+
+        let reloaded = MutesStore::load(Some(path.clone())).unwrap();
+        assert!(reloaded.list()[0].suppressed.is_empty());
+
+        store.flush();
+        let reloaded = MutesStore::load(Some(path.clone())).unwrap();
+        assert_eq!(reloaded.list(), store.list());
+        assert!(!path.with_extension("json.tmp").exists());
+    }
+
+    #[test]
+    fn test_summary_lists_suppressed_events() {
+        let mute = Mute {
+            target: MuteTarget::Repo("owner/repo".to_string()),
+            event_types: vec![],
+            until: Utc::now(),
+            suppressed: BTreeMap::from([("issues".to_string(), 1), ("push".to_string(), 3)]),
+        };
+
+        assert_eq!(
+            mute.summary(),
+            "Unmuted `owner/repo`. Suppressed while muted: 1 × `issues`, 3 × `push`."
+        );
+    }
+}
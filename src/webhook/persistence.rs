@@ -0,0 +1,41 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tracing::error;
+
+/// Reads the JSON file at `path`. Returns the default value if there's no such file.
+pub fn load<T: DeserializeOwned + Default>(path: Option<&Path>) -> Result<T> {
+    let Some(path) = path.filter(|path| path.exists()) else {
+        return Ok(T::default());
+    };
+
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_str(&contents).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// Writes `value` as JSON to `path`, creating its directory if needed. The file is written next
+/// to `path` first and then moved into place, so that a crash while writing can't leave a
+/// truncated file behind. Errors are logged since they shouldn't stop the service, the state is
+/// just not persisted.
+pub fn save<T: Serialize + ?Sized>(path: Option<&Path>, value: &T) {
+    let Some(path) = path else {
+        return;
+    };
+
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+
+    let result = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&temp_path, serde_json::to_vec_pretty(value)?))
+        .and_then(|_| fs::rename(&temp_path, path));
+
+    if let Err(err) = result {
+        error!("Failed to save {}: {err}", path.display());
+    }
+}
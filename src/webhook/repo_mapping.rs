@@ -2,20 +2,187 @@ use std::collections::HashMap;
 use std::sync::Arc;
 
 use prose_xmpp::BareJid;
+use tokio::sync::watch;
 
 use crate::config::RepoSettings;
+use crate::services::XMPPService;
+use crate::webhook::SubscriptionsStore;
 
+/// Maps repositories to the rooms their events are posted in. Repositories come from the
+/// configuration file and from the subscriptions made at runtime. The configuration file takes
+/// precedence.
 #[derive(Debug, Clone)]
-pub struct RepoMapping(Arc<HashMap<String, BareJid>>);
+pub struct RepoMapping {
+    configured: Arc<HashMap<String, RepoSettings>>,
+    subscriptions: SubscriptionsStore,
+    rooms: Arc<watch::Sender<Vec<BareJid>>>,
+}
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum SubscriptionError {
+    #[error("`{0}` is configured in the configuration file and can't be changed at runtime.")]
+    Configured(String),
+    #[error("`{0}` isn't subscribed to.")]
+    NotSubscribed(String),
+}
 
 impl RepoMapping {
     pub fn new(mapping: Vec<RepoSettings>) -> Self {
-        Self(Arc::new(
-            mapping.into_iter().map(|m| (m.repo, m.room)).collect(),
-        ))
+        Self::with_subscriptions(mapping, SubscriptionsStore::default())
+    }
+
+    pub fn with_subscriptions(
+        mapping: Vec<RepoSettings>,
+        subscriptions: SubscriptionsStore,
+    ) -> Self {
+        let repo_mapping = Self {
+            configured: Arc::new(mapping.into_iter().map(|m| (m.repo.clone(), m)).collect()),
+            subscriptions,
+            rooms: Arc::new(watch::Sender::new(vec![])),
+        };
+        repo_mapping.rooms.send_replace(repo_mapping.rooms());
+        repo_mapping
+    }
+
+    pub fn get(&self, repo: &str) -> Option<RepoSettings> {
+        self.configured
+            .get(repo)
+            .cloned()
+            .or_else(|| self.subscriptions.get(repo))
+    }
+
+    /// Returns the settings of all configured and subscribed repositories.
+    pub fn all(&self) -> Vec<RepoSettings> {
+        let subscriptions = self
+            .subscriptions
+            .list()
+            .into_iter()
+            .filter(|settings| !self.configured.contains_key(&settings.repo));
+        self.configured
+            .values()
+            .cloned()
+            .chain(subscriptions)
+            .collect()
+    }
+
+    /// Returns the rooms events are posted in, i.e. the rooms the bot needs to be in.
+    pub fn rooms(&self) -> Vec<BareJid> {
+        let mut rooms = self
+            .all()
+            .into_iter()
+            .flat_map(|settings| [Some(settings.room), settings.security_room])
+            .flatten()
+            .collect::<Vec<_>>();
+        rooms.sort_by_key(ToString::to_string);
+        rooms.dedup();
+        rooms
+    }
+
+    /// Subscribes to the events of `settings.repo`, replacing an earlier subscription.
+    pub fn subscribe(&self, settings: RepoSettings) -> Result<(), SubscriptionError> {
+        if self.configured.contains_key(&settings.repo) {
+            return Err(SubscriptionError::Configured(settings.repo));
+        }
+        self.subscriptions.subscribe(settings);
+        self.rooms.send_replace(self.rooms());
+        Ok(())
+    }
+
+    /// Removes the subscription of `repo` and returns its settings.
+    pub fn unsubscribe(&self, repo: &str) -> Result<RepoSettings, SubscriptionError> {
+        if self.configured.contains_key(repo) {
+            return Err(SubscriptionError::Configured(repo.to_string()));
+        }
+        let settings = self
+            .subscriptions
+            .unsubscribe(repo)
+            .ok_or_else(|| SubscriptionError::NotSubscribed(repo.to_string()))?;
+        self.rooms.send_replace(self.rooms());
+        Ok(settings)
+    }
+
+    /// Returns a receiver that is notified whenever the rooms change.
+    pub fn watch_rooms(&self) -> watch::Receiver<Vec<BareJid>> {
+        self.rooms.subscribe()
+    }
+}
+
+/// Keeps the rooms the bot is in in sync with the rooms events are posted in.
+pub async fn sync_rooms(mapping: RepoMapping, xmpp: Arc<dyn XMPPService>) {
+    let mut rooms = mapping.watch_rooms();
+
+    loop {
+        let current = rooms.borrow_and_update().clone();
+        xmpp.set_rooms(current);
+
+        if rooms.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(repo: &str, room: &str) -> RepoSettings {
+        RepoSettings {
+            repo: repo.to_string(),
+            room: room.parse().unwrap(),
+            security_room: None,
+            announce_ping: false,
+            announce_published_releases: false,
+            fallback_template: None,
+            locale: None,
+        }
+    }
+
+    #[test]
+    fn test_subscriptions_extend_configured_repos() {
+        let mapping = RepoMapping::new(vec![settings("owner/configured", "room1@example.org")]);
+        let mut rooms = mapping.watch_rooms();
+
+        mapping
+            .subscribe(settings("owner/subscribed", "room2@example.org"))
+            .unwrap();
+
+        assert!(rooms.has_changed().unwrap());
+        assert_eq!(
+            *rooms.borrow_and_update(),
+            vec![
+                "room1@example.org".parse::<BareJid>().unwrap(),
+                "room2@example.org".parse().unwrap()
+            ]
+        );
+        assert_eq!(mapping.all().len(), 2);
+
+        mapping.unsubscribe("owner/subscribed").unwrap();
+        assert!(mapping.get("owner/subscribed").is_none());
+        assert_eq!(
+            *rooms.borrow(),
+            vec!["room1@example.org".parse::<BareJid>().unwrap()]
+        );
     }
 
-    pub fn get(&self, room: &str) -> Option<&BareJid> {
-        self.0.get(room)
+    #[test]
+    fn test_configured_repos_cannot_be_changed() {
+        let mapping = RepoMapping::new(vec![settings("owner/configured", "room@example.org")]);
+
+        assert_eq!(
+            mapping.subscribe(settings("owner/configured", "other@example.org")),
+            Err(SubscriptionError::Configured(
+                "owner/configured".to_string()
+            ))
+        );
+        assert_eq!(
+            mapping.unsubscribe("owner/configured"),
+            Err(SubscriptionError::Configured(
+                "owner/configured".to_string()
+            ))
+        );
+        assert_eq!(
+            mapping.unsubscribe("owner/other"),
+            Err(SubscriptionError::NotSubscribed("owner/other".to_string()))
+        );
     }
 }
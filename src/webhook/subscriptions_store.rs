@@ -0,0 +1,112 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+
+use crate::config::RepoSettings;
+use crate::webhook::persistence;
+
+/// A store for the repositories subscribed to at runtime, in addition to the ones in the
+/// configuration file. Subscriptions are persisted to a file so that they survive restarts.
+#[derive(Debug, Clone, Default)]
+pub struct SubscriptionsStore {
+    path: Option<PathBuf>,
+    subscriptions: Arc<Mutex<Vec<RepoSettings>>>,
+}
+
+impl SubscriptionsStore {
+    /// Loads the subscriptions persisted at `path`. Subscriptions are kept in memory only if
+    /// `path` is `None`.
+    pub fn load(path: Option<PathBuf>) -> Result<Self> {
+        let subscriptions = persistence::load(path.as_deref())?;
+
+        Ok(Self {
+            path,
+            subscriptions: Arc::new(Mutex::new(subscriptions)),
+        })
+    }
+
+    pub fn get(&self, repo: &str) -> Option<RepoSettings> {
+        self.subscriptions
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|settings| settings.repo == repo)
+            .cloned()
+    }
+
+    pub fn list(&self) -> Vec<RepoSettings> {
+        self.subscriptions.lock().unwrap().clone()
+    }
+
+    /// Adds a subscription or replaces the existing subscription of the same repository.
+    pub fn subscribe(&self, settings: RepoSettings) {
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        subscriptions.retain(|subscription| subscription.repo != settings.repo);
+        subscriptions.push(settings);
+        persistence::save(self.path.as_deref(), &*subscriptions);
+    }
+
+    /// Removes and returns the subscription of `repo`, if any.
+    pub fn unsubscribe(&self, repo: &str) -> Option<RepoSettings> {
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        let idx = subscriptions
+            .iter()
+            .position(|subscription| subscription.repo == repo)?;
+        let settings = subscriptions.remove(idx);
+        persistence::save(self.path.as_deref(), &*subscriptions);
+        Some(settings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(repo: &str, room: &str) -> RepoSettings {
+        RepoSettings {
+            repo: repo.to_string(),
+            room: room.parse().unwrap(),
+            security_room: None,
+            announce_ping: false,
+            announce_published_releases: false,
+            fallback_template: None,
+            locale: None,
+        }
+    }
+
+    #[test]
+    fn test_subscribe_replaces_existing_subscription() {
+        let store = SubscriptionsStore::load(None).unwrap();
+        store.subscribe(settings("owner/repo", "room1@example.org"));
+        store.subscribe(settings("owner/repo", "room2@example.org"));
+
+        assert_eq!(store.list().len(), 1);
+        assert_eq!(
+            store.get("owner/repo").unwrap().room,
+            "room2@example.org".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_unsubscribe_removes_subscription() {
+        let store = SubscriptionsStore::load(None).unwrap();
+        store.subscribe(settings("owner/repo", "room@example.org"));
+
+        assert!(store.unsubscribe("owner/repo").is_some());
+        assert!(store.unsubscribe("owner/repo").is_none());
+        assert!(store.get("owner/repo").is_none());
+    }
+
+    #[test]
+    fn test_subscriptions_are_persisted() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("subscriptions.json");
+
+        let store = SubscriptionsStore::load(Some(path.clone())).unwrap();
+        store.subscribe(settings("owner/repo", "room@example.org"));
+
+        let reloaded = SubscriptionsStore::load(Some(path.clone())).unwrap();
+        assert_eq!(reloaded.list(), store.list());
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long a subject without new messages is remembered. Later messages start a new thread.
+const MAX_THREAD_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+/// The number of subjects that are remembered. The least recently active ones are forgotten first.
+const MAX_THREADS: usize = 1000;
+
+/// A store that remembers the first message sent about each issue, pull request or discussion, so
+/// that follow-up messages can be sent as replies to it.
+#[derive(Debug, Clone)]
+pub struct ThreadsStore {
+    threads: Arc<Mutex<HashMap<SubjectKey, Thread>>>,
+    /// The number of messages recorded so far. Orders the threads by activity, since consecutive
+    /// `Instant`s may be equal.
+    messages_sent: Arc<AtomicU64>,
+    max_age: Duration,
+    max_threads: usize,
+}
+
+#[derive(Debug)]
+struct Thread {
+    root_message_id: String,
+    last_message_at: Instant,
+    /// The value of `messages_sent` when the last message about the subject was recorded.
+    last_message_number: u64,
+}
+
+impl ThreadsStore {
+    pub fn new() -> Self {
+        Self::with_limits(MAX_THREAD_AGE, MAX_THREADS)
+    }
+
+    /// Creates a store that forgets subjects without messages for `max_age` and remembers at
+    /// most `max_threads` subjects.
+    pub fn with_limits(max_age: Duration, max_threads: usize) -> Self {
+        Self {
+            threads: Default::default(),
+            messages_sent: Default::default(),
+            max_age,
+            max_threads,
+        }
+    }
+
+    /// Records that the message with id `message_id` is about an issue, pull request or
+    /// discussion. Returns the id of the first message about it, unless that is this message.
+    ///
+    /// # Arguments
+    /// * `repo` - The repository name.
+    /// * `number` - The number of the issue, pull request or discussion within the repository.
+    /// * `message_id` - The id of the message.
+    pub fn message_sent(
+        &self,
+        repo: impl Into<String>,
+        number: u64,
+        message_id: impl Into<String>,
+    ) -> Option<String> {
+        let mut threads = self.threads.lock().unwrap();
+        let key = SubjectKey {
+            repo: repo.into(),
+            number,
+        };
+        let now = Instant::now();
+        let message_number = self.messages_sent.fetch_add(1, Ordering::Relaxed);
+
+        threads.retain(|_, thread| now.duration_since(thread.last_message_at) < self.max_age);
+
+        if let Some(thread) = threads.get_mut(&key) {
+            thread.last_message_at = now;
+            thread.last_message_number = message_number;
+            return Some(thread.root_message_id.clone());
+        }
+
+        threads.insert(
+            key,
+            Thread {
+                root_message_id: message_id.into(),
+                last_message_at: now,
+                last_message_number: message_number,
+            },
+        );
+        self.prune(&mut threads);
+        None
+    }
+
+    /// Removes the least recently active subjects beyond `max_threads`.
+    fn prune(&self, threads: &mut HashMap<SubjectKey, Thread>) {
+        let excess = threads.len().saturating_sub(self.max_threads);
+        if excess == 0 {
+            return;
+        }
+
+        let mut by_activity = threads
+            .iter()
+            .map(|(key, thread)| (thread.last_message_number, key.clone()))
+            .collect::<Vec<_>>();
+        by_activity.sort_by_key(|(number, _)| *number);
+
+        for (_, key) in by_activity.into_iter().take(excess) {
+            threads.remove(&key);
+        }
+    }
+}
+
+impl Default for ThreadsStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Issues, pull requests and discussions share their numbers within a repository.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct SubjectKey {
+    repo: String,
+    number: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_message_is_root() {
+        let store = ThreadsStore::new();
+        assert_eq!(store.message_sent("repo1", 1, "msg1"), None);
+        assert_eq!(
+            store.message_sent("repo1", 1, "msg2"),
+            Some("msg1".to_string())
+        );
+        assert_eq!(
+            store.message_sent("repo1", 1, "msg3"),
+            Some("msg1".to_string())
+        );
+    }
+
+    #[test]
+    fn test_subjects_are_separate() {
+        let store = ThreadsStore::new();
+        store.message_sent("repo1", 1, "msg1");
+        assert_eq!(store.message_sent("repo1", 2, "msg2"), None);
+        assert_eq!(store.message_sent("repo2", 1, "msg3"), None);
+    }
+
+    #[test]
+    fn test_inactive_subjects_are_forgotten() {
+        let store = ThreadsStore::with_limits(Duration::ZERO, 10);
+        store.message_sent("repo1", 1, "msg1");
+        assert_eq!(store.message_sent("repo1", 1, "msg2"), None);
+    }
+
+    #[test]
+    fn test_least_recently_active_subjects_are_evicted() {
+        let store = ThreadsStore::with_limits(MAX_THREAD_AGE, 2);
+        store.message_sent("repo1", 1, "msg1");
+        store.message_sent("repo1", 2, "msg2");
+        // A reply keeps the first subject active.
+        store.message_sent("repo1", 1, "msg3");
+        store.message_sent("repo1", 3, "msg4");
+
+        assert_eq!(store.threads.lock().unwrap().len(), 2);
+        assert_eq!(
+            store.message_sent("repo1", 1, "msg5"),
+            Some("msg1".to_string())
+        );
+        assert_eq!(store.message_sent("repo1", 2, "msg6"), None);
+    }
+}
@@ -1,44 +1,78 @@
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-/// A store for managing workflow runs associated with repositories.
+/// A store for managing workflow runs associated with repositories, along with the messages that
+/// reported their failure. Later runs of the same workflow on the same branch correct these
+/// messages (XEP-0308), so that the room shows a single evolving status line.
 #[derive(Debug, Clone)]
-pub struct WorkflowRunsStore(Arc<Mutex<HashMap<String, Vec<WorkflowRun>>>>);
+pub struct WorkflowRunsStore {
+    max_correction_age: Duration,
+    runs: Arc<Mutex<HashMap<String, Vec<WorkflowRun>>>>,
+}
+
+/// How the outcome of a workflow run is reported.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Report {
+    /// Send a new message.
+    New,
+    /// Correct the message with this id.
+    Correct(String),
+}
 
 impl WorkflowRunsStore {
-    pub fn new() -> Self {
-        Self(Default::default())
+    /// Creates a store whose messages are corrected for up to `max_correction_age` after they
+    /// were sent. Older messages are left alone and a new message is sent instead.
+    pub fn new(max_correction_age: Duration) -> Self {
+        Self {
+            max_correction_age,
+            runs: Default::default(),
+        }
     }
 
-    /// Records a failed workflow run for the specified repository. If the run is already recorded,
-    /// it does not add a duplicate.
+    /// Records a failed workflow run for the specified repository, reported by the message with
+    /// id `message_id`. If the run is already recorded, it does not add a duplicate but returns
+    /// the message to correct, unless that message is too old.
     ///
     /// # Arguments
     /// * `repo` - The repository name.
     /// * `workflow_id` - A unique identifier for the workflow run.
     /// * `head_branch` - The branch name at the head during the workflow run.
+    /// * `message_id` - The id of the message that is going to report the failure.
     pub fn workflow_failed(
         &self,
         repo: impl Into<String>,
         workflow_id: u64,
         head_branch: impl Into<String>,
-    ) {
-        let run = WorkflowRun {
-            workflow_id,
-            head_branch: head_branch.into(),
-        };
-        let mut map = self.0.lock().unwrap();
+        message_id: impl Into<String>,
+    ) -> Report {
+        let head_branch = head_branch.into();
+        let mut map = self.runs.lock().unwrap();
         let runs = map.entry(repo.into()).or_default();
 
-        if runs.contains(&run) {
-            return;
+        if let Some(run) = runs
+            .iter_mut()
+            .find(|run| run.workflow_id == workflow_id && run.head_branch == head_branch)
+        {
+            let report = self.report(run);
+            if report == Report::New {
+                run.message_id = message_id.into();
+                run.reported_at = Instant::now();
+            }
+            return report;
         }
 
-        runs.push(run);
+        runs.push(WorkflowRun {
+            workflow_id,
+            head_branch,
+            message_id: message_id.into(),
+            reported_at: Instant::now(),
+        });
+        Report::New
     }
 
     /// Removes workflow runs from the store that match the specified `workflow_id` and `head_branch`.
-    /// Returns `true` if any runs were removed, otherwise `false`.
+    /// Returns how to report the success if any runs were removed, otherwise `None`.
     ///
     /// # Arguments
     /// * `repo` - The repository name.
@@ -49,17 +83,25 @@ impl WorkflowRunsStore {
         repo: impl AsRef<str>,
         workflow_id: u64,
         head_branch: impl AsRef<str>,
-    ) -> bool {
-        let mut map = self.0.lock().unwrap();
-        let Some(runs) = map.get_mut(repo.as_ref()) else {
-            return false;
-        };
+    ) -> Option<Report> {
+        let mut map = self.runs.lock().unwrap();
+        let runs = map.get_mut(repo.as_ref())?;
 
-        let runs_len = runs.len();
         let head_branch = head_branch.as_ref();
-        runs.retain(|run| run.workflow_id != workflow_id || &run.head_branch != head_branch);
+        let idx = runs
+            .iter()
+            .position(|run| run.workflow_id == workflow_id && run.head_branch == head_branch)?;
+        let run = runs.remove(idx);
+
+        Some(self.report(&run))
+    }
 
-        runs.len() != runs_len
+    fn report(&self, run: &WorkflowRun) -> Report {
+        if run.reported_at.elapsed() < self.max_correction_age {
+            Report::Correct(run.message_id.clone())
+        } else {
+            Report::New
+        }
     }
 }
 
@@ -67,53 +109,80 @@ impl WorkflowRunsStore {
 struct WorkflowRun {
     workflow_id: u64,
     head_branch: String,
+    message_id: String,
+    reported_at: Instant,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    const MAX_AGE: Duration = Duration::from_secs(3600);
+
     #[test]
     fn test_new_store_is_empty() {
-        let store = WorkflowRunsStore::new();
-        let map = store.0.lock().unwrap();
+        let store = WorkflowRunsStore::new(MAX_AGE);
+        let map = store.runs.lock().unwrap();
         assert!(map.is_empty());
     }
 
     #[test]
     fn test_workflow_failed_adds_run() {
-        let store = WorkflowRunsStore::new();
-        store.workflow_failed("repo1", 1, "main");
-        let map = store.0.lock().unwrap();
+        let store = WorkflowRunsStore::new(MAX_AGE);
+        assert_eq!(
+            store.workflow_failed("repo1", 1, "main", "msg1"),
+            Report::New
+        );
+        let map = store.runs.lock().unwrap();
         assert_eq!(map.get("repo1").unwrap().len(), 1);
     }
 
     #[test]
     fn test_workflow_failed_does_not_add_duplicate() {
-        let store = WorkflowRunsStore::new();
-        store.workflow_failed("repo1", 1, "main");
-        store.workflow_failed("repo1", 1, "main"); // Attempt to add duplicate
-        let map = store.0.lock().unwrap();
+        let store = WorkflowRunsStore::new(MAX_AGE);
+        store.workflow_failed("repo1", 1, "main", "msg1");
+        let report = store.workflow_failed("repo1", 1, "main", "msg2"); // Attempt to add duplicate
+        assert_eq!(report, Report::Correct("msg1".to_string()));
+        let map = store.runs.lock().unwrap();
         assert_eq!(map.get("repo1").unwrap().len(), 1);
     }
 
+    #[test]
+    fn test_workflow_failed_reports_new_message_if_previous_is_too_old() {
+        let store = WorkflowRunsStore::new(Duration::ZERO);
+        store.workflow_failed("repo1", 1, "main", "msg1");
+        assert_eq!(
+            store.workflow_failed("repo1", 1, "main", "msg2"),
+            Report::New
+        );
+        assert_eq!(store.runs.lock().unwrap()["repo1"][0].message_id, "msg2");
+    }
+
     #[test]
     fn test_workflow_succeeded_removes_correct_run() {
-        let store = WorkflowRunsStore::new();
-        store.workflow_failed("repo1", 1, "main");
-        store.workflow_failed("repo1", 2, "dev");
-        let removed = store.workflow_succeeded("repo1", 1, "main");
-        let map = store.0.lock().unwrap();
-        assert!(removed);
+        let store = WorkflowRunsStore::new(MAX_AGE);
+        store.workflow_failed("repo1", 1, "main", "msg1");
+        store.workflow_failed("repo1", 2, "dev", "msg2");
+        let report = store.workflow_succeeded("repo1", 1, "main");
+        let map = store.runs.lock().unwrap();
+        assert_eq!(report, Some(Report::Correct("msg1".to_string())));
         assert_eq!(map.get("repo1").unwrap().len(), 1);
         assert_eq!(map.get("repo1").unwrap()[0].workflow_id, 2);
     }
 
     #[test]
-    fn test_workflow_succeeded_returns_false_if_no_match() {
-        let store = WorkflowRunsStore::new();
-        store.workflow_failed("repo1", 1, "main");
-        let removed = store.workflow_succeeded("repo1", 999, "main");
-        assert!(!removed);
+    fn test_workflow_succeeded_returns_none_if_no_match() {
+        let store = WorkflowRunsStore::new(MAX_AGE);
+        store.workflow_failed("repo1", 1, "main", "msg1");
+        let report = store.workflow_succeeded("repo1", 999, "main");
+        assert_eq!(report, None);
+    }
+
+    #[test]
+    fn test_workflow_succeeded_reports_new_message_if_failure_is_too_old() {
+        let store = WorkflowRunsStore::new(Duration::ZERO);
+        store.workflow_failed("repo1", 1, "main", "msg1");
+        let report = store.workflow_succeeded("repo1", 1, "main");
+        assert_eq!(report, Some(Report::New));
     }
 }
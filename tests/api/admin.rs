@@ -0,0 +1,326 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use reqwest::StatusCode;
+use xmpp_webhook::config::AdminSettings;
+
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+#[tokio::test]
+async fn test_preview_is_disabled_without_admin_settings() -> Result<()> {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(&format!("{}/admin/preview", &app.address))
+        .basic_auth("admin", Some("secret"))
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_preview_requires_credentials() -> Result<()> {
+    let app = spawn_app_with_admin().await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .get(&format!("{}/admin/preview", &app.address))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(response.headers().contains_key("WWW-Authenticate"));
+
+    let response = client
+        .get(&format!("{}/admin/preview", &app.address))
+        .basic_auth("admin", Some("wrong"))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = client
+        .get(&format!("{}/admin/preview", &app.address))
+        .basic_auth("admin", Some("secret"))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.text().await?.contains("<option>push.md</option>"));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_preview_renders_sample_payload() -> Result<()> {
+    let app = spawn_app_with_admin().await;
+
+    let response = reqwest::Client::new()
+        .post(&format!("{}/admin/preview", &app.address))
+        .basic_auth("admin", Some("secret"))
+        .form(&[("template", "push.md"), ("sample", "push"), ("payload", "")])
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.text().await?;
+    assert!(body.contains("Message styling fallback"));
+    assert!(body.contains("refactor: Allow accessing ServerFeatures"));
+    assert!(app.xmpp.sent_messages().is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_preview_shows_errors() -> Result<()> {
+    let app = spawn_app_with_admin().await;
+
+    let response = reqwest::Client::new()
+        .post(&format!("{}/admin/preview", &app.address))
+        .basic_auth("admin", Some("secret"))
+        .form(&[("template", "push.md"), ("payload", "{ not json")])
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.text().await?.contains("Invalid payload"));
+
+    Ok(())
+}
+
+async fn spawn_app_with_admin() -> TestApp {
+    spawn_app_with(|config| {
+        config.admin = Some(AdminSettings {
+            username: "admin".to_string(),
+            password: "secret".to_string(),
+        })
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_overly_long_mute_is_rejected() -> Result<()> {
+    let app = spawn_app_with_admin().await;
+
+    let response = reqwest::Client::new()
+        .post(&format!("{}/admin/mutes", &app.address))
+        .basic_auth("admin", Some("secret"))
+        .json(&serde_json::json!({
+            "target": { "repo": "prose-im/prose-core-client" },
+            "duration_secs": u64::MAX,
+        }))
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_mute_expires_with_summary() -> Result<()> {
+    let app = spawn_app_with_admin().await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(&format!("{}/admin/mutes", &app.address))
+        .basic_auth("admin", Some("secret"))
+        .json(&serde_json::json!({
+            "target": { "repo": "prose-im/prose-core-client" },
+            "duration_secs": 2,
+        }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let mutes: serde_json::Value = client
+        .get(&format!("{}/admin/mutes", &app.address))
+        .basic_auth("admin", Some("secret"))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(mutes.as_array().map(Vec::len), Some(1));
+
+    client
+        .post(&format!("{}/webhook", &app.address))
+        .header("X-GitHub-Event", "push")
+        .header("Content-Type", "application/json")
+        .body(include_str!("../../samples/push.json"))
+        .send()
+        .await?;
+    assert!(app.xmpp.sent_messages().is_empty());
+
+    tokio::time::sleep(Duration::from_secs(4)).await;
+
+    let sent_messages = app.xmpp.sent_messages();
+    assert_eq!(sent_messages.len(), 1);
+    assert_eq!(
+        sent_messages[0].message,
+        "Unmuted `prose-im/prose-core-client`. Suppressed while muted: 1 × `push`."
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_unmute_of_unmuted_room_is_not_found() -> Result<()> {
+    let app = spawn_app_with_admin().await;
+
+    let response = reqwest::Client::new()
+        .delete(&format!("{}/admin/mutes", &app.address))
+        .basic_auth("admin", Some("secret"))
+        .json(&serde_json::json!({ "target": { "room": "room@example.org" } }))
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_subscriptions_can_be_managed() -> Result<()> {
+    let app = spawn_app_with_admin().await;
+    let client = reqwest::Client::new();
+    let url = format!("{}/admin/subscriptions", &app.address);
+
+    let response = client
+        .post(&url)
+        .basic_auth("admin", Some("secret"))
+        .json(&serde_json::json!({
+            "repo": "nesium/other",
+            "room": "other@example.org",
+            "security_room": "other-security@example.org",
+        }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let repos: serde_json::Value = client
+        .get(&url)
+        .basic_auth("admin", Some("secret"))
+        .send()
+        .await?
+        .json()
+        .await?;
+    assert_eq!(repos.as_array().map(Vec::len), Some(4));
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let rooms = app.xmpp.rooms();
+    assert!(rooms.contains(&"other@example.org".parse()?));
+    assert!(rooms.contains(&"other-security@example.org".parse()?));
+
+    let response = client
+        .delete(&url)
+        .basic_auth("admin", Some("secret"))
+        .json(&serde_json::json!({ "repo": "nesium/other" }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = client
+        .delete(&url)
+        .basic_auth("admin", Some("secret"))
+        .json(&serde_json::json!({ "repo": "nesium/test_repo" }))
+        .send()
+        .await?;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!app.xmpp.rooms().contains(&"other@example.org".parse()?));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_rooms_report_membership_state() -> Result<()> {
+    let app = spawn_app_with_admin().await;
+    let client = reqwest::Client::new();
+    app.xmpp
+        .make_unreachable("unreachable@example.org".parse().unwrap());
+
+    client
+        .post(&format!("{}/admin/subscriptions", &app.address))
+        .basic_auth("admin", Some("secret"))
+        .json(&serde_json::json!({
+            "repo": "nesium/other",
+            "room": "unreachable@example.org",
+        }))
+        .send()
+        .await?
+        .error_for_status()?;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let rooms: serde_json::Value = client
+        .get(&format!("{}/admin/rooms", &app.address))
+        .basic_auth("admin", Some("secret"))
+        .send()
+        .await?
+        .json()
+        .await?;
+
+    assert_eq!(
+        rooms,
+        serde_json::json!([
+            { "room": "room@example.org", "state": "joined" },
+            { "room": "security@example.org", "state": "joined" },
+            {
+                "room": "unreachable@example.org",
+                "state": "rejoining",
+                "reason": "remote-server-not-found",
+                "attempt": 0,
+            },
+        ])
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_only_rooms_invited_to_can_be_left() -> Result<()> {
+    let app = spawn_app_with_admin().await;
+
+    let response = reqwest::Client::new()
+        .delete(&format!("{}/admin/rooms", &app.address))
+        .basic_auth("admin", Some("secret"))
+        .json(&serde_json::json!({ "room": "room@example.org" }))
+        .send()
+        .await?;
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_deliveries_are_listed_with_counts() -> Result<()> {
+    let app = spawn_app_with_admin().await;
+    let client = reqwest::Client::new();
+
+    client
+        .post(&format!("{}/webhook", &app.address))
+        .header("X-GitHub-Event", "push")
+        .header("Content-Type", "application/json")
+        .body(include_str!("../../samples/push.json"))
+        .send()
+        .await?
+        .error_for_status()?;
+
+    let response: serde_json::Value = client
+        .get(&format!("{}/admin/deliveries", &app.address))
+        .basic_auth("admin", Some("secret"))
+        .send()
+        .await?
+        .json()
+        .await?;
+
+    assert_eq!(response["counts"], serde_json::json!({ "delivered": 1 }));
+    assert_eq!(
+        response["deliveries"][0]["id"],
+        app.xmpp.sent_messages()[0].id.as_str()
+    );
+    assert_eq!(response["deliveries"][0]["status"], "delivered");
+
+    Ok(())
+}
@@ -0,0 +1,285 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use xmpp_webhook::services::xmpp_service::{Affiliation, IncomingMessage, RoomId};
+
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+#[tokio::test]
+async fn test_regular_messages_are_ignored() {
+    let app = spawn_app().await;
+
+    let reply = app.xmpp.receive_message(room_message("Hello bot!", None));
+
+    assert_eq!(reply, None);
+}
+
+#[tokio::test]
+async fn test_help_lists_commands() {
+    let app = spawn_app().await;
+
+    let reply = app
+        .xmpp
+        .receive_message(room_message("!help", None))
+        .unwrap();
+
+    assert!(reply.contains("!help"));
+    assert!(reply.contains("!status"));
+}
+
+#[tokio::test]
+async fn test_unknown_command_is_reported_if_addressed() {
+    let app = spawn_app().await;
+
+    let reply = app.xmpp.receive_message(IncomingMessage {
+        is_addressed: true,
+        ..room_message("!deploy", None)
+    });
+
+    assert_eq!(
+        reply.as_deref(),
+        Some("Unknown command `!deploy`. Send `!help` for a list of commands.")
+    );
+}
+
+#[tokio::test]
+async fn test_unknown_commands_of_other_bots_are_ignored() {
+    let app = spawn_app().await;
+
+    let reply = app.xmpp.receive_message(room_message("!deploy", None));
+
+    assert_eq!(reply, None);
+}
+
+#[tokio::test]
+async fn test_status_lists_repos_of_room() {
+    let app = spawn_app().await;
+
+    let reply = app
+        .xmpp
+        .receive_message(IncomingMessage {
+            room: RoomId::Room("security@example.org".parse().unwrap()),
+            ..room_message("!status", Some(Affiliation::Member))
+        })
+        .unwrap();
+
+    assert_eq!(
+        reply,
+        "Events of these repositories are posted here:\n- Codertocat/Hello-World"
+    );
+}
+
+#[tokio::test]
+async fn test_status_requires_membership() {
+    let app = spawn_app().await;
+
+    let reply = app
+        .xmpp
+        .receive_message(room_message("!status", Some(Affiliation::None)));
+
+    assert_eq!(
+        reply.as_deref(),
+        Some("You are not allowed to run this command.")
+    );
+}
+
+#[tokio::test]
+async fn test_configured_admins_may_run_commands_directly() {
+    let app = spawn_app_with(|config| {
+        config.xmpp.admins = vec!["admin@example.org".parse().unwrap()];
+    })
+    .await;
+
+    let direct_message = |sender: &str| IncomingMessage {
+        room: RoomId::User(sender.parse().unwrap()),
+        sender: Some(sender.parse().unwrap()),
+        affiliation: None,
+        is_addressed: true,
+        body: "!status".to_string(),
+    };
+
+    let reply = app
+        .xmpp
+        .receive_message(direct_message("admin@example.org"))
+        .unwrap();
+    assert_eq!(reply.lines().count(), 4);
+
+    let reply = app
+        .xmpp
+        .receive_message(direct_message("someone@example.org"));
+    assert_eq!(
+        reply.as_deref(),
+        Some("You are not allowed to run this command.")
+    );
+}
+
+fn room_message(body: &str, affiliation: Option<Affiliation>) -> IncomingMessage {
+    IncomingMessage {
+        room: RoomId::Room("room@example.org".parse().unwrap()),
+        sender: None,
+        affiliation,
+        is_addressed: false,
+        body: body.to_string(),
+    }
+}
+
+#[tokio::test]
+async fn test_mute_suppresses_events_until_unmuted() -> Result<()> {
+    let app = spawn_app().await;
+
+    let reply = app
+        .xmpp
+        .receive_message(room_message("!mute push 2h", Some(Affiliation::Member)))
+        .unwrap();
+    assert!(reply.starts_with("Muted `push` events here until "));
+
+    post_webhook(&app, "push", include_str!("../../samples/push.json")).await?;
+    post_webhook(
+        &app,
+        "issues",
+        include_str!("../../samples/issue_opened.json"),
+    )
+    .await?;
+
+    let sent_messages = app.xmpp.sent_messages();
+    assert_eq!(sent_messages.len(), 1);
+    assert!(sent_messages[0].message.contains("opened"));
+
+    let reply = app
+        .xmpp
+        .receive_message(room_message("!unmute", Some(Affiliation::Member)));
+    assert_eq!(
+        reply.as_deref(),
+        Some("Unmuted this room. Suppressed while muted: 1 × `push`.")
+    );
+
+    post_webhook(&app, "push", include_str!("../../samples/push.json")).await?;
+    assert_eq!(app.xmpp.sent_messages().len(), 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_overly_long_mute_is_rejected() {
+    let app = spawn_app().await;
+
+    let reply = app
+        .xmpp
+        .receive_message(room_message("!mute 100000000d", Some(Affiliation::Member)));
+
+    assert_eq!(
+        reply.as_deref(),
+        Some("Failed to mute: Mutes can last at most 90 days.")
+    );
+}
+
+#[tokio::test]
+async fn test_mute_of_repo_not_reported_in_room_is_rejected() {
+    let app = spawn_app().await;
+
+    let reply = app.xmpp.receive_message(room_message(
+        "!mute nesium/other 2h",
+        Some(Affiliation::Member),
+    ));
+
+    assert_eq!(
+        reply.as_deref(),
+        Some("`nesium/other` isn't reported here.")
+    );
+}
+
+async fn post_webhook(app: &TestApp, event_type: &str, body: &'static str) -> Result<()> {
+    reqwest::Client::new()
+        .post(&format!("{}/webhook", &app.address))
+        .header("X-GitHub-Event", event_type)
+        .header("Content-Type", "application/json")
+        .body(body)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+fn admin_message(body: &str) -> IncomingMessage {
+    IncomingMessage {
+        sender: Some("admin@example.org".parse().unwrap()),
+        ..room_message(body, Some(Affiliation::Member))
+    }
+}
+
+async fn spawn_app_with_admin() -> TestApp {
+    spawn_app_with(|config| {
+        config.xmpp.admins = vec!["admin@example.org".parse().unwrap()];
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_subscribe_enters_and_unsubscribe_leaves_room() {
+    let app = spawn_app_with_admin().await;
+    let other_room = "other@example.org".parse().unwrap();
+
+    let reply = app
+        .xmpp
+        .receive_message(admin_message("!subscribe nesium/other other@example.org"));
+    assert_eq!(
+        reply.as_deref(),
+        Some("Events of `nesium/other` are now posted in other@example.org.")
+    );
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(app.xmpp.rooms().contains(&other_room));
+
+    let reply = app
+        .xmpp
+        .receive_message(admin_message("!unsubscribe nesium/other"));
+    assert_eq!(
+        reply.as_deref(),
+        Some("Events of `nesium/other` are no longer posted in other@example.org.")
+    );
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!app.xmpp.rooms().contains(&other_room));
+}
+
+#[tokio::test]
+async fn test_subscriptions_require_configured_admin() {
+    let app = spawn_app_with_admin().await;
+
+    for affiliation in [Affiliation::Member, Affiliation::Admin, Affiliation::Owner] {
+        let reply = app.xmpp.receive_message(room_message(
+            "!subscribe nesium/other other@example.org",
+            Some(affiliation),
+        ));
+        assert_eq!(
+            reply.as_deref(),
+            Some("You are not allowed to run this command.")
+        );
+    }
+
+    let reply = app.xmpp.receive_message(room_message(
+        "!unsubscribe Codertocat/Hello-World",
+        Some(Affiliation::Owner),
+    ));
+    assert_eq!(
+        reply.as_deref(),
+        Some("You are not allowed to run this command.")
+    );
+}
+
+#[tokio::test]
+async fn test_configured_repos_cannot_be_unsubscribed() {
+    let app = spawn_app_with_admin().await;
+
+    let reply = app
+        .xmpp
+        .receive_message(admin_message("!unsubscribe nesium/test_repo"));
+
+    assert_eq!(
+        reply.as_deref(),
+        Some(
+            "`nesium/test_repo` is configured in the configuration file and can't be changed at \
+            runtime."
+        )
+    );
+}
@@ -1,8 +1,14 @@
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
+use anyhow::bail;
+use prose_xmpp::{BareJid, FullJid};
 use xmpp_webhook::app::App;
-use xmpp_webhook::config::{get_configuration, RepoSettings};
-use xmpp_webhook::services::xmpp_service::RoomId;
+use xmpp_webhook::config::{get_configuration, RepoSettings, Settings};
+use xmpp_webhook::services::rooms::{RoomConnector, Rooms};
+use xmpp_webhook::services::xmpp_service::{
+    Delivery, DeliveryStatus, IncomingMessage, MessageHandler, OutgoingMessage, RoomId, RoomStatus,
+};
 use xmpp_webhook::services::XMPPService;
 
 #[ctor::ctor]
@@ -16,6 +22,10 @@ pub struct TestApp {
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+pub async fn spawn_app_with(configure: impl FnOnce(&mut Settings)) -> TestApp {
     let mut config = get_configuration().expect("Failed to read configuration");
     // Use a random OS port
     config.app.port = 0;
@@ -23,16 +33,32 @@ pub async fn spawn_app() -> TestApp {
         RepoSettings {
             repo: "Codertocat/Hello-World".to_string(),
             room: "room@example.org".parse().unwrap(),
+            security_room: Some("security@example.org".parse().unwrap()),
+            announce_ping: false,
+            announce_published_releases: false,
+            fallback_template: None,
+            locale: None,
         },
         RepoSettings {
             repo: "prose-im/prose-core-client".to_string(),
             room: "room@example.org".parse().unwrap(),
+            security_room: None,
+            announce_ping: false,
+            announce_published_releases: false,
+            fallback_template: None,
+            locale: None,
         },
         RepoSettings {
             repo: "nesium/test_repo".to_string(),
             room: "room@example.org".parse().unwrap(),
+            security_room: None,
+            announce_ping: true,
+            announce_published_releases: false,
+            fallback_template: None,
+            locale: None,
         },
     ];
+    configure(&mut config);
 
     let xmpp = MockXMPPService::default();
 
@@ -46,9 +72,23 @@ pub async fn spawn_app() -> TestApp {
     TestApp { address, xmpp }
 }
 
-#[derive(Default, Clone)]
+#[derive(Clone)]
 pub struct MockXMPPService {
     inner: Arc<Mutex<MockXMPPServiceInner>>,
+    rooms: Rooms,
+    connector: MockRoomConnector,
+}
+
+impl Default for MockXMPPService {
+    fn default() -> Self {
+        Self {
+            inner: Default::default(),
+            // Failed rooms stay in their first rejoin attempt for the duration of a test.
+            rooms: Rooms::new(vec![])
+                .with_backoff(Duration::from_secs(60), Duration::from_secs(60)),
+            connector: Default::default(),
+        }
+    }
 }
 
 impl MockXMPPService {
@@ -59,25 +99,105 @@ impl MockXMPPService {
     pub fn reset_sent_messages(&self) {
         self.inner.lock().unwrap().sent_messages.clear();
     }
+
+    /// The rooms the service was last told to be in.
+    pub fn rooms(&self) -> Vec<BareJid> {
+        self.inner.lock().unwrap().rooms.clone()
+    }
+
+    /// Makes entering `room` fail from now on.
+    pub fn make_unreachable(&self, room: BareJid) {
+        self.connector.unreachable_rooms.lock().unwrap().push(room);
+    }
+
+    /// Simulates receiving `message` and returns the reply, if any.
+    pub fn receive_message(&self, message: IncomingMessage) -> Option<String> {
+        let handler = self.inner.lock().unwrap().message_handler.clone();
+        handler.and_then(|handler| handler.handle_message(message))
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct SentMessage {
     pub to: RoomId,
+    pub id: String,
     pub message: String,
+    pub replaces: Option<String>,
+    pub reply_to: Option<String>,
+    pub thread: Option<String>,
 }
 
 impl XMPPService for MockXMPPService {
-    fn send_message(&self, to: RoomId, message: String) {
-        self.inner
+    fn send_message(&self, message: OutgoingMessage) {
+        self.inner.lock().unwrap().sent_messages.push(SentMessage {
+            to: message.to,
+            id: message.id,
+            message: message.body,
+            replaces: message.replaces,
+            reply_to: message.reply_to,
+            thread: message.thread,
+        })
+    }
+
+    fn set_message_handler(&self, handler: Arc<dyn MessageHandler>) {
+        self.inner.lock().unwrap().message_handler = Some(handler);
+    }
+
+    fn set_rooms(&self, rooms: Vec<BareJid>) {
+        self.inner.lock().unwrap().rooms = rooms.clone();
+        self.rooms.set_rooms(&self.connector, rooms);
+    }
+
+    fn deliveries(&self) -> Vec<Delivery> {
+        self.sent_messages()
+            .into_iter()
+            .map(|message| Delivery {
+                id: message.id,
+                to: message.to.jid().clone(),
+                status: DeliveryStatus::Delivered,
+                attempts: 1,
+                sent_at: chrono::Utc::now(),
+            })
+            .collect()
+    }
+
+    fn room_states(&self) -> Vec<RoomStatus> {
+        self.rooms.states()
+    }
+}
+
+/// Enters rooms right away, apart from the ones in `unreachable_rooms`.
+#[derive(Default, Clone)]
+struct MockRoomConnector {
+    unreachable_rooms: Arc<Mutex<Vec<BareJid>>>,
+}
+
+impl RoomConnector for MockRoomConnector {
+    async fn enter_room(
+        &self,
+        occupant: &FullJid,
+        _password: Option<&str>,
+        _status: Option<&str>,
+    ) -> anyhow::Result<()> {
+        if self
+            .unreachable_rooms
             .lock()
             .unwrap()
-            .sent_messages
-            .push(SentMessage { to, message })
+            .contains(&occupant.to_bare())
+        {
+            bail!("remote-server-not-found");
+        }
+        Ok(())
+    }
+
+    fn exit_room(&self, _occupant: &FullJid) -> anyhow::Result<()> {
+        Ok(())
     }
 }
 
 #[derive(Default)]
 struct MockXMPPServiceInner {
     sent_messages: Vec<SentMessage>,
+    message_handler: Option<Arc<dyn MessageHandler>>,
+    rooms: Vec<BareJid>,
 }
@@ -1,2 +1,4 @@
+mod admin;
+mod commands;
 mod helpers;
 mod webhook;
@@ -1,12 +1,14 @@
-use crate::helpers::{spawn_app, SentMessage};
+use crate::helpers::{spawn_app, spawn_app_with, SentMessage};
 use anyhow::Result;
 use insta::assert_snapshot;
 use reqwest::{Body, StatusCode};
+use std::time::Duration;
+use xmpp_webhook::services::xmpp_service::RoomId;
 
 #[tokio::test]
 async fn test_push() -> Result<()> {
     let (status, sent_messages) =
-        receive_webhook("push", include_str!("fixtures/push.json")).await?;
+        receive_webhook("push", include_str!("../../samples/push.json")).await?;
 
     assert!(status.is_success());
     assert_snapshot!(sent_messages[0].message);
@@ -17,7 +19,7 @@ async fn test_push() -> Result<()> {
 #[tokio::test]
 async fn test_force_push() -> Result<()> {
     let (status, sent_messages) =
-        receive_webhook("push", include_str!("fixtures/push_forced.json")).await?;
+        receive_webhook("push", include_str!("../../samples/push_forced.json")).await?;
 
     assert!(status.is_success());
     assert_snapshot!(sent_messages[0].message);
@@ -28,8 +30,11 @@ async fn test_force_push() -> Result<()> {
 #[tokio::test]
 /// These can happen when pushing a new tag
 async fn test_ignores_push_with_empty_commits() -> Result<()> {
-    let (status, sent_messages) =
-        receive_webhook("push", include_str!("fixtures/push_without_commits.json")).await?;
+    let (status, sent_messages) = receive_webhook(
+        "push",
+        include_str!("../../samples/push_without_commits.json"),
+    )
+    .await?;
 
     assert!(status.is_success());
     assert!(sent_messages.is_empty());
@@ -37,10 +42,68 @@ async fn test_ignores_push_with_empty_commits() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_push_with_many_commits_is_shortened() -> Result<()> {
+    let app = spawn_app_with(|config| config.xmpp.max_message_size = 2000).await;
+    let client = reqwest::Client::new();
+
+    let mut event: serde_json::Value =
+        serde_json::from_str(include_str!("../../samples/push.json"))?;
+    event["commits"] = serde_json::json!(vec![event["commits"][0].clone(); 25]);
+
+    let response = client
+        .post(&format!("{}/webhook", &app.address))
+        .header("X-GitHub-Event", "push")
+        .header("Content-Type", "application/json")
+        .body(serde_json::to_string(&event)?)
+        .send()
+        .await?;
+
+    assert!(response.status().is_success());
+
+    let sent_messages = app.xmpp.sent_messages();
+    assert!(sent_messages[0].message.len() <= 2000);
+    assert_snapshot!(sent_messages[0].message);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_push_uses_localized_template() -> Result<()> {
+    let templates_dir = tempfile::tempdir()?;
+    std::fs::write(
+        templates_dir.path().join("push.fr.md.j2"),
+        "{{ event.commits | length | pluralize('commit') }} le {{ event.head_commit.timestamp | date }}",
+    )?;
+
+    let app = spawn_app_with(|config| {
+        config.app.templates_dir = Some(templates_dir.path().to_path_buf());
+        config.webhook.repos[1].locale = Some("fr".to_string());
+    })
+    .await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(&format!("{}/webhook", &app.address))
+        .header("X-GitHub-Event", "push")
+        .header("Content-Type", "application/json")
+        .body(include_str!("../../samples/push.json"))
+        .send()
+        .await?;
+
+    assert!(response.status().is_success());
+    assert_eq!(
+        app.xmpp.sent_messages()[0].message,
+        "4 commits le 27 juin 2024"
+    );
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_issue_opened() -> Result<()> {
     let (status, sent_messages) =
-        receive_webhook("issues", include_str!("fixtures/issue_opened.json")).await?;
+        receive_webhook("issues", include_str!("../../samples/issue_opened.json")).await?;
 
     assert!(status.is_success());
     assert_snapshot!(sent_messages[0].message);
@@ -51,7 +114,7 @@ async fn test_issue_opened() -> Result<()> {
 #[tokio::test]
 async fn test_issue_closed() -> Result<()> {
     let (status, sent_messages) =
-        receive_webhook("issues", include_str!("fixtures/issue_closed.json")).await?;
+        receive_webhook("issues", include_str!("../../samples/issue_closed.json")).await?;
 
     assert!(status.is_success());
     assert_snapshot!(sent_messages[0].message);
@@ -63,7 +126,7 @@ async fn test_issue_closed() -> Result<()> {
 async fn test_issue_comment_created() -> Result<()> {
     let (status, sent_messages) = receive_webhook(
         "issue_comment",
-        include_str!("fixtures/issue_comment_created.json"),
+        include_str!("../../samples/issue_comment_created.json"),
     )
     .await?;
 
@@ -77,7 +140,7 @@ async fn test_issue_comment_created() -> Result<()> {
 async fn test_workflow_run_completed_with_failure() -> Result<()> {
     let (status, sent_messages) = receive_webhook(
         "workflow_run",
-        include_str!("fixtures/workflow_run_completed_failure.json"),
+        include_str!("../../samples/workflow_run_completed_failure.json"),
     )
     .await?;
 
@@ -91,7 +154,7 @@ async fn test_workflow_run_completed_with_failure() -> Result<()> {
 async fn test_workflow_run_completed_with_success_does_not_send_message() -> Result<()> {
     let (status, sent_messages) = receive_webhook(
         "workflow_run",
-        include_str!("fixtures/workflow_run_completed_success.json"),
+        include_str!("../../samples/workflow_run_completed_success.json"),
     )
     .await?;
 
@@ -105,7 +168,7 @@ async fn test_workflow_run_completed_with_success_does_not_send_message() -> Res
 async fn test_workflow_run_completed_with_cancellation_does_not_send_message() -> Result<()> {
     let (status, sent_messages) = receive_webhook(
         "workflow_run",
-        include_str!("fixtures/workflow_run_completed_cancelled.json"),
+        include_str!("../../samples/workflow_run_completed_cancelled.json"),
     )
     .await?;
 
@@ -124,11 +187,14 @@ async fn test_workflow_run_completed_with_success_sends_message_after_failure()
         .post(&format!("{}/webhook", &app.address))
         .header("X-GitHub-Event", "workflow_run")
         .header("Content-Type", "application/json")
-        .body(include_str!("fixtures/workflow_run_completed_failure.json"))
+        .body(include_str!(
+            "../../samples/workflow_run_completed_failure.json"
+        ))
         .send()
         .await?;
 
-    assert_snapshot!(app.xmpp.sent_messages()[0].message);
+    let failure_message = app.xmpp.sent_messages()[0].clone();
+    assert_snapshot!(failure_message.message);
 
     app.xmpp.reset_sent_messages();
 
@@ -136,19 +202,142 @@ async fn test_workflow_run_completed_with_success_sends_message_after_failure()
         .post(&format!("{}/webhook", &app.address))
         .header("X-GitHub-Event", "workflow_run")
         .header("Content-Type", "application/json")
-        .body(include_str!("fixtures/workflow_run_completed_success.json"))
+        .body(include_str!(
+            "../../samples/workflow_run_completed_success.json"
+        ))
         .send()
         .await?;
 
-    assert_snapshot!(app.xmpp.sent_messages()[0].message);
+    let success_message = app.xmpp.sent_messages()[0].clone();
+    assert_eq!(success_message.replaces, Some(failure_message.id));
+    assert_snapshot!(success_message.message);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_workflow_run_failing_again_corrects_previous_message() -> Result<()> {
+    let app = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    for _ in 0..2 {
+        client
+            .post(&format!("{}/webhook", &app.address))
+            .header("X-GitHub-Event", "workflow_run")
+            .header("Content-Type", "application/json")
+            .body(include_str!(
+                "../../samples/workflow_run_completed_failure.json"
+            ))
+            .send()
+            .await?;
+    }
+
+    let sent_messages = app.xmpp.sent_messages();
+    assert_eq!(sent_messages.len(), 2);
+    assert_eq!(sent_messages[0].replaces, None);
+    assert_eq!(sent_messages[1].replaces, Some(sent_messages[0].id.clone()));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_workflow_run_sends_new_message_if_failure_is_too_old_to_correct() -> Result<()> {
+    let app = spawn_app_with(|config| config.webhook.workflow_correction_max_age_secs = 0).await;
+    let client = reqwest::Client::new();
+
+    for fixture in [
+        include_str!("../../samples/workflow_run_completed_failure.json"),
+        include_str!("../../samples/workflow_run_completed_success.json"),
+    ] {
+        client
+            .post(&format!("{}/webhook", &app.address))
+            .header("X-GitHub-Event", "workflow_run")
+            .header("Content-Type", "application/json")
+            .body(fixture)
+            .send()
+            .await?;
+    }
+
+    let sent_messages = app.xmpp.sent_messages();
+    assert_eq!(sent_messages.len(), 2);
+    assert_eq!(sent_messages[1].replaces, None);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_follow_up_events_reply_to_first_message() -> Result<()> {
+    let app = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    for (event_type, fixture) in [
+        (
+            "discussion",
+            include_str!("../../samples/discussion_created.json"),
+        ),
+        (
+            "discussion_comment",
+            include_str!("../../samples/discussion_comment_created.json"),
+        ),
+        (
+            "discussion",
+            include_str!("../../samples/discussion_answered.json"),
+        ),
+    ] {
+        client
+            .post(&format!("{}/webhook", &app.address))
+            .header("X-GitHub-Event", event_type)
+            .header("Content-Type", "application/json")
+            .body(fixture)
+            .send()
+            .await?;
+    }
+
+    let sent_messages = app.xmpp.sent_messages();
+    assert_eq!(sent_messages.len(), 3);
+    assert_eq!(sent_messages[0].reply_to, None);
+    assert_eq!(sent_messages[1].reply_to, Some(sent_messages[0].id.clone()));
+    assert_eq!(sent_messages[2].reply_to, Some(sent_messages[0].id.clone()));
+
+    for message in &sent_messages {
+        assert_eq!(message.thread.as_deref(), Some("Codertocat/Hello-World#90"));
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_events_without_subject_are_not_threaded() -> Result<()> {
+    let (_, sent_messages) =
+        receive_webhook("push", include_str!("../../samples/push.json")).await?;
+
+    assert_eq!(sent_messages[0].reply_to, None);
+    assert_eq!(sent_messages[0].thread, None);
 
     Ok(())
 }
 
 #[tokio::test]
 async fn test_release_released() -> Result<()> {
-    let (status, sent_messages) =
-        receive_webhook("release", include_str!("fixtures/release_released.json")).await?;
+    let (status, sent_messages) = receive_webhook(
+        "release",
+        include_str!("../../samples/release_released.json"),
+    )
+    .await?;
+
+    assert!(status.is_success());
+    assert_snapshot!(sent_messages[0].message);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_release_released_with_assets() -> Result<()> {
+    let (status, sent_messages) = receive_webhook(
+        "release",
+        include_str!("../../samples/release_released_with_assets.json"),
+    )
+    .await?;
 
     assert!(status.is_success());
     assert_snapshot!(sent_messages[0].message);
@@ -156,10 +345,234 @@ async fn test_release_released() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_release_published_is_ignored() -> Result<()> {
+    let mut event: serde_json::Value =
+        serde_json::from_str(include_str!("../../samples/release_released.json"))?;
+    event["action"] = "published".into();
+
+    let (status, sent_messages) =
+        receive_webhook("release", serde_json::to_string(&event)?).await?;
+
+    assert!(status.is_success());
+    assert!(sent_messages.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_release_published_can_be_announced_instead() -> Result<()> {
+    let app = spawn_app_with(|config| {
+        for settings in &mut config.webhook.repos {
+            settings.announce_published_releases = true;
+        }
+    })
+    .await;
+    let client = reqwest::Client::new();
+
+    let mut event: serde_json::Value =
+        serde_json::from_str(include_str!("../../samples/release_released.json"))?;
+    for action in ["published", "released"] {
+        event["action"] = action.into();
+        client
+            .post(&format!("{}/webhook", &app.address))
+            .header("X-GitHub-Event", "release")
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_string(&event)?)
+            .send()
+            .await?
+            .error_for_status()?;
+    }
+
+    let sent_messages = app.xmpp.sent_messages();
+    assert_eq!(sent_messages.len(), 1);
+
+    let (_, released_messages) = receive_webhook(
+        "release",
+        include_str!("../../samples/release_released.json"),
+    )
+    .await?;
+    assert_eq!(sent_messages[0].message, released_messages[0].message);
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_release_prereleased() -> Result<()> {
+    let (status, sent_messages) = receive_webhook(
+        "release",
+        include_str!("../../samples/release_prereleased.json"),
+    )
+    .await?;
+
+    assert!(status.is_success());
+    assert_snapshot!(sent_messages[0].message);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_dependabot_alert_created() -> Result<()> {
+    let (status, sent_messages) = receive_webhook(
+        "dependabot_alert",
+        include_str!("../../samples/dependabot_alert_created.json"),
+    )
+    .await?;
+
+    assert!(status.is_success());
+    assert_eq!(
+        sent_messages[0].to,
+        RoomId::Room("security@example.org".parse()?)
+    );
+    assert_snapshot!(sent_messages[0].message);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_code_scanning_alert_created() -> Result<()> {
+    let (status, sent_messages) = receive_webhook(
+        "code_scanning_alert",
+        include_str!("../../samples/code_scanning_alert_created.json"),
+    )
+    .await?;
+
+    assert!(status.is_success());
+    assert_eq!(
+        sent_messages[0].to,
+        RoomId::Room("security@example.org".parse()?)
+    );
+    assert_snapshot!(sent_messages[0].message);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_secret_scanning_alert_created() -> Result<()> {
+    let (status, sent_messages) = receive_webhook(
+        "secret_scanning_alert",
+        include_str!("../../samples/secret_scanning_alert_created.json"),
+    )
+    .await?;
+
+    assert!(status.is_success());
+    assert_eq!(
+        sent_messages[0].to,
+        RoomId::Room("security@example.org".parse()?)
+    );
+    assert_snapshot!(sent_messages[0].message);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_repository_vulnerability_alert_create() -> Result<()> {
+    let (status, sent_messages) = receive_webhook(
+        "repository_vulnerability_alert",
+        include_str!("../../samples/repository_vulnerability_alert_create.json"),
+    )
+    .await?;
+
+    assert!(status.is_success());
+    assert_eq!(
+        sent_messages[0].to,
+        RoomId::Room("security@example.org".parse()?)
+    );
+    assert_snapshot!(sent_messages[0].message);
+
+    Ok(())
+}
+
+#[tokio::test]
+/// Security alerts must never end up in the (possibly public) default room of a repo.
+async fn test_security_alert_is_dropped_without_security_room() -> Result<()> {
+    let mut event: serde_json::Value =
+        serde_json::from_str(include_str!("../../samples/dependabot_alert_created.json"))?;
+    event["repository"]["full_name"] = "prose-im/prose-core-client".into();
+
+    let (status, sent_messages) =
+        receive_webhook("dependabot_alert", serde_json::to_string(&event)?).await?;
+
+    assert!(status.is_success());
+    assert!(sent_messages.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_discussion_created() -> Result<()> {
+    let (status, sent_messages) = receive_webhook(
+        "discussion",
+        include_str!("../../samples/discussion_created.json"),
+    )
+    .await?;
+
+    assert!(status.is_success());
+    assert_snapshot!(sent_messages[0].message);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_discussion_answered() -> Result<()> {
+    let (status, sent_messages) = receive_webhook(
+        "discussion",
+        include_str!("../../samples/discussion_answered.json"),
+    )
+    .await?;
+
+    assert!(status.is_success());
+    assert_snapshot!(sent_messages[0].message);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_discussion_closed() -> Result<()> {
+    let (status, sent_messages) = receive_webhook(
+        "discussion",
+        include_str!("../../samples/discussion_closed.json"),
+    )
+    .await?;
+
+    assert!(status.is_success());
+    assert_snapshot!(sent_messages[0].message);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_discussion_category_changed() -> Result<()> {
+    let (status, sent_messages) = receive_webhook(
+        "discussion",
+        include_str!("../../samples/discussion_category_changed.json"),
+    )
+    .await?;
+
+    assert!(status.is_success());
+    assert_snapshot!(sent_messages[0].message);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_discussion_comment_created() -> Result<()> {
+    let (status, sent_messages) = receive_webhook(
+        "discussion_comment",
+        include_str!("../../samples/discussion_comment_created.json"),
+    )
+    .await?;
+
+    assert!(status.is_success());
+    assert_snapshot!(sent_messages[0].message);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_issue_labeled() -> Result<()> {
     let (status, sent_messages) =
-        receive_webhook("release", include_str!("fixtures/release_prereleased.json")).await?;
+        receive_webhook("issues", include_str!("../../samples/issue_labeled.json")).await?;
 
     assert!(status.is_success());
     assert_snapshot!(sent_messages[0].message);
@@ -167,6 +580,228 @@ async fn test_release_prereleased() -> Result<()> {
     Ok(())
 }
 
+#[tokio::test]
+async fn test_issue_unlabeled() -> Result<()> {
+    let (status, sent_messages) =
+        receive_webhook("issues", include_str!("../../samples/issue_unlabeled.json")).await?;
+
+    assert!(status.is_success());
+    assert_snapshot!(sent_messages[0].message);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_issue_assigned() -> Result<()> {
+    let (status, sent_messages) =
+        receive_webhook("issues", include_str!("../../samples/issue_assigned.json")).await?;
+
+    assert!(status.is_success());
+    assert_snapshot!(sent_messages[0].message);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_issue_unassigned() -> Result<()> {
+    let (status, sent_messages) = receive_webhook(
+        "issues",
+        include_str!("../../samples/issue_unassigned.json"),
+    )
+    .await?;
+
+    assert!(status.is_success());
+    assert_snapshot!(sent_messages[0].message);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_issue_milestoned() -> Result<()> {
+    let (status, sent_messages) = receive_webhook(
+        "issues",
+        include_str!("../../samples/issue_milestoned.json"),
+    )
+    .await?;
+
+    assert!(status.is_success());
+    assert_snapshot!(sent_messages[0].message);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_issue_transferred() -> Result<()> {
+    let (status, sent_messages) = receive_webhook(
+        "issues",
+        include_str!("../../samples/issue_transferred.json"),
+    )
+    .await?;
+
+    assert!(status.is_success());
+    assert_snapshot!(sent_messages[0].message);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_issue_edited() -> Result<()> {
+    let (status, sent_messages) =
+        receive_webhook("issues", include_str!("../../samples/issue_edited.json")).await?;
+
+    assert!(status.is_success());
+    assert_snapshot!(sent_messages[0].message);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_rapid_label_changes_are_collapsed() -> Result<()> {
+    let app = spawn_app_with(|config| config.webhook.label_changes_delay_secs = 1).await;
+    let client = reqwest::Client::new();
+
+    let labeled: serde_json::Value =
+        serde_json::from_str(include_str!("../../samples/issue_labeled.json"))?;
+    let unlabeled: serde_json::Value =
+        serde_json::from_str(include_str!("../../samples/issue_unlabeled.json"))?;
+
+    for (event, label) in [
+        (&labeled, "enhancement"),
+        (&labeled, "help wanted"),
+        (&unlabeled, "bug"),
+        (&labeled, "wontfix"),
+        (&unlabeled, "wontfix"),
+    ] {
+        let mut event = event.clone();
+        event["label"]["name"] = label.into();
+
+        client
+            .post(&format!("{}/webhook", &app.address))
+            .header("X-GitHub-Event", "issues")
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_string(&event)?)
+            .send()
+            .await?;
+    }
+
+    assert!(app.xmpp.sent_messages().is_empty());
+
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+
+    let sent_messages = app.xmpp.sent_messages();
+    assert_eq!(sent_messages.len(), 1);
+    assert_snapshot!(sent_messages[0].message);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_ping_is_acknowledged_and_announced() -> Result<()> {
+    let app = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(&format!("{}/webhook", &app.address))
+        .header("X-GitHub-Event", "ping")
+        .header("Content-Type", "application/json")
+        .body(include_str!("../../samples/ping.json"))
+        .send()
+        .await?;
+
+    assert!(response.status().is_success());
+
+    let body = response.json::<serde_json::Value>().await?;
+    assert_eq!(body["hook_id"], 486719634);
+    assert_eq!(body["zen"], "Keep it logically awesome.");
+    assert_eq!(
+        body["unsupported_events"],
+        serde_json::json!(["pull_request"])
+    );
+
+    assert_snapshot!(app.xmpp.sent_messages()[0].message);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_ping_for_unknown_repo_is_acknowledged() -> Result<()> {
+    let mut event: serde_json::Value =
+        serde_json::from_str(include_str!("../../samples/ping.json"))?;
+    event["repository"]["full_name"] = "nesium/unknown_repo".into();
+
+    let (status, sent_messages) = receive_webhook("ping", serde_json::to_string(&event)?).await?;
+
+    assert!(status.is_success());
+    assert!(sent_messages.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_issue_title_is_escaped() -> Result<()> {
+    let mut event: serde_json::Value =
+        serde_json::from_str(include_str!("../../samples/issue_opened.json"))?;
+    event["issue"]["title"] = "Click [here](https://evil.example) for `free` *stuff*".into();
+
+    let (status, sent_messages) = receive_webhook("issues", serde_json::to_string(&event)?).await?;
+
+    assert!(status.is_success());
+    assert_snapshot!(sent_messages[0].message);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_commit_message_cannot_close_code_block() -> Result<()> {
+    let mut event: serde_json::Value =
+        serde_json::from_str(include_str!("../../samples/push.json"))?;
+    event["commits"] = serde_json::json!([event["commits"][0]]);
+    event["commits"][0]["message"] = "Fix\n```\n[injected](https://evil.example)\n```".into();
+
+    let (status, sent_messages) = receive_webhook("push", serde_json::to_string(&event)?).await?;
+
+    assert!(status.is_success());
+    assert_snapshot!(sent_messages[0].message);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_event_without_template_uses_fallback_template() -> Result<()> {
+    let app = spawn_app_with(|config| {
+        config.webhook.repos[0].fallback_template = Some("_default.md".to_string())
+    })
+    .await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(&format!("{}/webhook", &app.address))
+        .header("X-GitHub-Event", "star")
+        .header("Content-Type", "application/json")
+        .body(include_str!("../../samples/star_created.json"))
+        .send()
+        .await?;
+
+    assert!(response.status().is_success());
+
+    let sent_messages = app.xmpp.sent_messages();
+    assert_eq!(sent_messages.len(), 1);
+    assert_snapshot!(sent_messages[0].message);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_event_without_template_is_dropped_without_fallback_template() -> Result<()> {
+    let (status, sent_messages) =
+        receive_webhook("star", include_str!("../../samples/star_created.json")).await?;
+
+    assert!(status.is_success());
+    assert!(sent_messages.is_empty());
+
+    Ok(())
+}
+
 async fn receive_webhook(
     event_type: impl AsRef<str>,
     body: impl Into<Body>,